@@ -1,2 +1,3 @@
 pub mod h5_writer;  
-pub mod idx_reader;
\ No newline at end of file
+pub mod idx_reader;
+pub mod svg_plot;
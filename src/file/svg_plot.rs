@@ -0,0 +1,363 @@
+use std::fs;
+
+use crate::graph::history::History;
+
+const COLORS: [&str; 8] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#17becf",
+];
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 160.0;
+const MARGIN_TOP: f64 = 40.0;
+const MARGIN_BOTTOM: f64 = 50.0;
+// Decades a log axis can span, within the range of a normal f64
+const MIN_EXPONENT: f64 = -307.0;
+const MAX_EXPONENT: f64 = 308.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Scale {
+    Linear,
+    // Base 10. Values that are not positive are left out of the plot.
+    Log,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlotOptions {
+    pub title: String,
+    pub width: usize,
+    pub height: usize,
+    pub y_scale: Scale,
+    // Names of the history series to draw, or every series except the learning rate when None
+    pub series: Option<Vec<String>>,
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        PlotOptions {
+            title: "Loss vs Epoch".to_string(),
+            width: 800,
+            height: 500,
+            y_scale: Scale::Linear,
+            series: None,
+        }
+    }
+}
+
+// Draws every selected series of a history as a line chart against the epoch, numbered from 1
+pub fn render_history(history: &History, options: &PlotOptions) -> String {
+    let names: Vec<String> = match &options.series {
+        Some(series) => series.clone(),
+        None => history
+            .names()
+            .into_iter()
+            .filter(|name| *name != "lr")
+            .map(|name| name.to_string())
+            .collect(),
+    };
+    let series: Vec<(&str, &[f64])> = names
+        .iter()
+        .filter_map(|name| history.get(name).map(|values| (name.as_str(), values)))
+        .collect();
+
+    let plottable = |value: f64| value.is_finite() && (options.y_scale == Scale::Linear || value > 0.0);
+    let values = series
+        .iter()
+        .flat_map(|(_, values)| values.iter().cloned())
+        .filter(|value| plottable(*value));
+    let (min, max) = values.fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+        (min.min(value), max.max(value))
+    });
+    // Nothing to plot, so any range will do as long as a log axis can take it
+    let (min, max) = match options.y_scale {
+        _ if min <= max => (min, max),
+        Scale::Linear => (0.0, 1.0),
+        Scale::Log => (1.0, 10.0),
+    };
+
+    let y_axis = Axis::new(min, max, options.y_scale);
+    let x_axis = Axis::new(1.0, history.epochs().max(2) as f64, Scale::Linear);
+    let plot_width = options.width as f64 - MARGIN_LEFT - MARGIN_RIGHT;
+    let plot_height = options.height as f64 - MARGIN_TOP - MARGIN_BOTTOM;
+    let x_position = |epoch: f64| MARGIN_LEFT + x_axis.fraction(epoch) * plot_width;
+    let y_position = |value: f64| MARGIN_TOP + (1.0 - y_axis.fraction(value)) * plot_height;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+        options.width, options.height, options.width, options.height
+    );
+    svg.push_str(&format!(
+        "<rect width=\"{}\" height=\"{}\" fill=\"white\"/>\n",
+        options.width, options.height
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\" font-size=\"16\">{}</text>\n",
+        MARGIN_LEFT + plot_width / 2.0,
+        MARGIN_TOP / 2.0 + 6.0,
+        escape(&options.title)
+    ));
+
+    // Grid lines and tick labels
+    for tick in y_axis.ticks() {
+        let y = y_position(tick);
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#e0e0e0\"/>\n",
+            MARGIN_LEFT,
+            y,
+            MARGIN_LEFT + plot_width,
+            y
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>\n",
+            MARGIN_LEFT - 6.0,
+            y + 4.0,
+            format_tick(tick)
+        ));
+    }
+    for tick in x_axis.ticks() {
+        let x = x_position(tick);
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"#e0e0e0\"/>\n",
+            x,
+            MARGIN_TOP,
+            x,
+            MARGIN_TOP + plot_height
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+            x,
+            MARGIN_TOP + plot_height + 18.0,
+            format_tick(tick)
+        ));
+    }
+
+    // Axes and their labels
+    svg.push_str(&format!(
+        "<polyline points=\"{:.1},{:.1} {:.1},{:.1} {:.1},{:.1}\" fill=\"none\" stroke=\"black\"/>\n",
+        MARGIN_LEFT,
+        MARGIN_TOP,
+        MARGIN_LEFT,
+        MARGIN_TOP + plot_height,
+        MARGIN_LEFT + plot_width,
+        MARGIN_TOP + plot_height
+    ));
+    svg.push_str(&format!(
+        "<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">Epoch</text>\n",
+        MARGIN_LEFT + plot_width / 2.0,
+        options.height as f64 - 12.0
+    ));
+    let y_label = match options.y_scale {
+        Scale::Linear => "Value",
+        Scale::Log => "Value (log scale)",
+    };
+    svg.push_str(&format!(
+        "<text x=\"16\" y=\"{:.1}\" text-anchor=\"middle\" transform=\"rotate(-90 16 {:.1})\">{}</text>\n",
+        MARGIN_TOP + plot_height / 2.0,
+        MARGIN_TOP + plot_height / 2.0,
+        y_label
+    ));
+
+    for (index, (name, values)) in series.iter().enumerate() {
+        let color = COLORS[index % COLORS.len()];
+
+        // Values that cannot be drawn split the line into separate segments
+        let mut segments: Vec<Vec<String>> = vec![vec![]];
+        for (epoch, value) in values.iter().enumerate() {
+            if plottable(*value) {
+                let point = format!("{:.1},{:.1}", x_position((epoch + 1) as f64), y_position(*value));
+                segments.last_mut().unwrap().push(point);
+            } else if !segments.last().unwrap().is_empty() {
+                segments.push(vec![]);
+            }
+        }
+        for segment in segments.iter().filter(|segment| !segment.is_empty()) {
+            svg.push_str(&format!(
+                "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+                segment.join(" "),
+                color
+            ));
+        }
+
+        let legend_x = MARGIN_LEFT + plot_width + 16.0;
+        let legend_y = MARGIN_TOP + 10.0 + index as f64 * 20.0;
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"2\"/>\n",
+            legend_x,
+            legend_y,
+            legend_x + 20.0,
+            legend_y,
+            color
+        ));
+        svg.push_str(&format!(
+            "<text x=\"{:.1}\" y=\"{:.1}\">{}</text>\n",
+            legend_x + 26.0,
+            legend_y + 4.0,
+            escape(name)
+        ));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn save_history_plot(history: &History, path: &str, options: &PlotOptions) -> std::io::Result<()> {
+    fs::write(path, render_history(history, options))
+}
+
+// Range of an axis, widened to round numbers
+struct Axis {
+    min: f64,
+    max: f64,
+    scale: Scale,
+}
+
+impl Axis {
+    fn new(min: f64, max: f64, scale: Scale) -> Axis {
+        match scale {
+            Scale::Linear => {
+                let (min, max) = if min == max { (min - 1.0, max + 1.0) } else { (min, max) };
+                let step = tick_step(min, max);
+                Axis {
+                    min: (min / step).floor() * step,
+                    max: (max / step).ceil() * step,
+                    scale,
+                }
+            }
+            Scale::Log => {
+                let min = min.log10().floor().clamp(MIN_EXPONENT, MAX_EXPONENT - 1.0);
+                let max = max.log10().ceil().clamp(min + 1.0, MAX_EXPONENT);
+                Axis {
+                    min: 10.0_f64.powf(min),
+                    max: 10.0_f64.powf(max),
+                    scale,
+                }
+            }
+        }
+    }
+
+    // Position of a value along the axis, from 0 at the minimum to 1 at the maximum
+    fn fraction(&self, value: f64) -> f64 {
+        match self.scale {
+            Scale::Linear => (value - self.min) / (self.max - self.min),
+            Scale::Log => (value.log10() - self.min.log10()) / (self.max.log10() - self.min.log10()),
+        }
+    }
+
+    fn ticks(&self) -> Vec<f64> {
+        match self.scale {
+            Scale::Linear => {
+                let step = tick_step(self.min, self.max);
+                let count = ((self.max - self.min) / step).round() as usize;
+                (0..=count).map(|i| self.min + i as f64 * step).collect()
+            }
+            Scale::Log => {
+                let exponent = |value: f64| value.log10().round().clamp(MIN_EXPONENT, MAX_EXPONENT) as i32;
+                let (min, max) = (exponent(self.min), exponent(self.max));
+                (min..=max).map(|power| 10.0_f64.powi(power)).collect()
+            }
+        }
+    }
+}
+
+// A step of 1, 2 or 5 times a power of ten that splits the range into at most ten parts
+fn tick_step(min: f64, max: f64) -> f64 {
+    let rough = (max - min) / 10.0;
+    let magnitude = 10.0_f64.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .iter()
+        .map(|factor| factor * magnitude)
+        .find(|step| *step >= rough)
+        .unwrap()
+}
+
+fn format_tick(value: f64) -> String {
+    if value != 0.0 && (value.abs() >= 1e5 || value.abs() < 1e-3) {
+        format!("{:e}", value)
+    } else {
+        // Rounds away floating point noise such as 0.30000000000000004
+        let rounded = (value * 1e6).round() / 1e6;
+        format!("{}", rounded)
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history() -> History {
+        let mut history = History::new();
+        for (epoch, loss) in [1.0, 0.5, 0.1, 0.05].iter().enumerate() {
+            history.record("loss", *loss);
+            history.record("val_loss", loss * 1.5);
+            history.record("lr", 0.01 / (epoch + 1) as f64);
+        }
+        history
+    }
+
+    #[test]
+    fn test_render_history() {
+        let svg = render_history(&history(), &PlotOptions::default());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(">loss</text>"));
+        assert!(svg.contains(">val_loss</text>"));
+        // The learning rate is on a different scale so it is left out unless asked for
+        assert!(!svg.contains(">lr</text>"));
+        assert_eq!(svg.matches("stroke-width=\"2\"/>").count(), 4);
+    }
+
+    #[test]
+    fn test_log_scale() {
+        let options = PlotOptions {
+            y_scale: Scale::Log,
+            series: Some(vec!["loss".to_string()]),
+            ..PlotOptions::default()
+        };
+        let svg = render_history(&history(), &options);
+        assert!(svg.contains("Value (log scale)"));
+        // Decades from 0.01 to 1
+        assert!(svg.contains(">0.01</text>"));
+        assert!(svg.contains(">0.1</text>"));
+        assert!(svg.contains(">1</text>"));
+    }
+
+    #[test]
+    fn test_log_scale_without_positive_values() {
+        let options = PlotOptions {
+            y_scale: Scale::Log,
+            ..PlotOptions::default()
+        };
+        let svg = render_history(&History::new(), &options);
+        assert!(svg.trim_end().ends_with("</svg>"));
+
+        let mut history = History::new();
+        for value in [0.0, -0.5, f64::NAN] {
+            history.record("r2", value);
+        }
+        let svg = render_history(&history, &options);
+        assert!(svg.contains(">1</text>"));
+        assert!(svg.contains(">10</text>"));
+    }
+
+    #[test]
+    fn test_axis_ticks() {
+        let axis = Axis::new(0.03, 0.97, Scale::Linear);
+        assert_eq!(axis.min, 0.0);
+        assert_eq!(axis.max, 1.0);
+        assert_eq!(axis.ticks().len(), 11);
+    }
+
+    #[test]
+    fn test_save_history_plot() {
+        let path = std::env::temp_dir().join("svg_plot_test_save_history_plot.svg");
+        let path = path.to_str().unwrap();
+        save_history_plot(&history(), path, &PlotOptions::default()).unwrap();
+        assert!(fs::read_to_string(path).unwrap().contains("<svg"));
+        fs::remove_file(path).unwrap();
+    }
+}
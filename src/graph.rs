@@ -1,4 +1,7 @@
 pub mod graph;
 pub mod optimizer;
+pub mod learning_rate_schedule;
 pub mod loss_function;
-pub mod network_metric;
\ No newline at end of file
+pub mod network_metric;
+pub mod history;
+pub mod callbacks;
\ No newline at end of file
@@ -0,0 +1,419 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Write,
+};
+
+use serde_json::{json, Value};
+
+use super::graph::Model;
+
+// Values reported to callbacks, keyed like History. Epoch logs hold the loss, every metric, their
+// validation counterparts and the learning rate. Batch logs hold the batch "size" and, once the
+// batch is done, its "loss".
+pub type Logs = BTreeMap<String, f64>;
+
+// Hooks into Sequential::fit. Epochs and batches are numbered from 0.
+pub trait Callback {
+    fn on_train_begin(&mut self, _model: &mut dyn Model, _logs: &Logs) {}
+    fn on_train_end(&mut self, _model: &mut dyn Model, _logs: &Logs) {}
+    fn on_epoch_begin(&mut self, _model: &mut dyn Model, _epoch: usize, _logs: &Logs) {}
+    fn on_epoch_end(&mut self, _model: &mut dyn Model, _epoch: usize, _logs: &Logs) {}
+    fn on_batch_begin(&mut self, _model: &mut dyn Model, _batch: usize, _logs: &Logs) {}
+    fn on_batch_end(&mut self, _model: &mut dyn Model, _batch: usize, _logs: &Logs) {}
+}
+
+// Whether the monitored value should go down or up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Min,
+    Max,
+}
+
+impl Mode {
+    // Max for scores such as accuracy, Min for losses and errors
+    pub fn for_metric(name: &str) -> Mode {
+        let name = name.strip_prefix("val_").unwrap_or(name);
+        let scores = ["accuracy", "precision", "recall", "f1", "roc_auc", "r2"];
+        if scores.iter().any(|score| name.contains(score)) {
+            Mode::Max
+        } else {
+            Mode::Min
+        }
+    }
+
+    fn improved(&self, value: f64, best: f64, min_delta: f64) -> bool {
+        match self {
+            Mode::Min => value < best - min_delta,
+            Mode::Max => value > best + min_delta,
+        }
+    }
+
+    fn worst(&self) -> f64 {
+        match self {
+            Mode::Min => f64::INFINITY,
+            Mode::Max => f64::NEG_INFINITY,
+        }
+    }
+}
+
+fn monitored_value(logs: &Logs, monitor: &str) -> f64 {
+    match logs.get(monitor) {
+        Some(value) => *value,
+        None => panic!(
+            "Cannot monitor {}, the logs only have {:?}",
+            monitor,
+            logs.keys().collect::<Vec<_>>()
+        ),
+    }
+}
+
+// Stops training once the monitored value has gone patience epochs without improving by more than
+// min_delta, optionally putting back the weights from the best epoch when training ends
+pub struct EarlyStopping {
+    pub monitor: String,
+    pub patience: usize,
+    pub min_delta: f64,
+    pub mode: Mode,
+    pub restore_best_weights: bool,
+    // Epoch training was stopped after, if it was
+    pub stopped_epoch: Option<usize>,
+    best: f64,
+    best_weights: Option<Vec<Vec<f64>>>,
+    epochs_without_improvement: usize,
+}
+
+impl EarlyStopping {
+    pub fn new(monitor: &str, patience: usize, restore_best_weights: bool) -> EarlyStopping {
+        let mode = Mode::for_metric(monitor);
+        EarlyStopping {
+            monitor: monitor.to_string(),
+            patience,
+            min_delta: 0.0,
+            mode,
+            restore_best_weights,
+            stopped_epoch: None,
+            best: mode.worst(),
+            best_weights: None,
+            epochs_without_improvement: 0,
+        }
+    }
+}
+
+impl Callback for EarlyStopping {
+    fn on_train_begin(&mut self, _model: &mut dyn Model, _logs: &Logs) {
+        self.stopped_epoch = None;
+        self.best = self.mode.worst();
+        self.best_weights = None;
+        self.epochs_without_improvement = 0;
+    }
+
+    fn on_epoch_end(&mut self, model: &mut dyn Model, epoch: usize, logs: &Logs) {
+        let value = monitored_value(logs, &self.monitor);
+        if self.mode.improved(value, self.best, self.min_delta) {
+            self.best = value;
+            self.epochs_without_improvement = 0;
+            if self.restore_best_weights {
+                self.best_weights = Some(model.get_weights());
+            }
+            return;
+        }
+
+        self.epochs_without_improvement += 1;
+        if self.epochs_without_improvement >= self.patience {
+            self.stopped_epoch = Some(epoch);
+            model.stop_training();
+        }
+    }
+
+    fn on_train_end(&mut self, model: &mut dyn Model, _logs: &Logs) {
+        if let Some(weights) = self.best_weights.take() {
+            model.set_weights(weights);
+        }
+    }
+}
+
+// Saves the model weights as JSON at the end of every epoch. A "{epoch}" in the path is replaced
+// with the epoch number, counting from 1. With save_best_only only epochs that improve the
+// monitored value are saved.
+pub struct ModelCheckpoint {
+    pub path: String,
+    pub monitor: String,
+    pub mode: Mode,
+    pub save_best_only: bool,
+    best: f64,
+}
+
+impl ModelCheckpoint {
+    pub fn new(path: &str, monitor: &str, save_best_only: bool) -> ModelCheckpoint {
+        let mode = Mode::for_metric(monitor);
+        ModelCheckpoint {
+            path: path.to_string(),
+            monitor: monitor.to_string(),
+            mode,
+            save_best_only,
+            best: mode.worst(),
+        }
+    }
+}
+
+impl Callback for ModelCheckpoint {
+    fn on_train_begin(&mut self, _model: &mut dyn Model, _logs: &Logs) {
+        self.best = self.mode.worst();
+    }
+
+    fn on_epoch_end(&mut self, model: &mut dyn Model, epoch: usize, logs: &Logs) {
+        if self.save_best_only {
+            let value = monitored_value(logs, &self.monitor);
+            if !self.mode.improved(value, self.best, 0.0) {
+                return;
+            }
+            self.best = value;
+        }
+
+        let path = self.path.replace("{epoch}", &(epoch + 1).to_string());
+        let checkpoint = json!({
+            "epoch": epoch + 1,
+            "logs": logs,
+            "weights": model.get_weights(),
+        });
+        if let Err(error) = fs::write(&path, checkpoint.to_string()) {
+            panic!("Could not write checkpoint to {}: {}", path, error);
+        }
+    }
+}
+
+// Reads back the weights written by ModelCheckpoint, ready for Model::set_weights
+pub fn load_weights(path: &str) -> Result<Vec<Vec<f64>>, &'static str> {
+    let contents = fs::read_to_string(path).map_err(|_| "Error reading file")?;
+    let checkpoint: Value = serde_json::from_str(&contents).map_err(|_| "Error parsing checkpoint")?;
+    checkpoint["weights"]
+        .as_array()
+        .and_then(|weights| {
+            weights
+                .iter()
+                .map(|parameter| {
+                    parameter
+                        .as_array()?
+                        .iter()
+                        .map(|value| value.as_f64())
+                        .collect::<Option<Vec<f64>>>()
+                })
+                .collect::<Option<Vec<Vec<f64>>>>()
+        })
+        .ok_or("Checkpoint has no weights")
+}
+
+// Sets the learning rate at the start of every epoch from the epoch number and the current rate.
+// A learning rate schedule set on the model takes precedence, since it is applied on every step.
+pub struct LearningRateScheduler {
+    schedule: Box<dyn Fn(usize, f64) -> f64>,
+}
+
+impl LearningRateScheduler {
+    pub fn new(schedule: Box<dyn Fn(usize, f64) -> f64>) -> LearningRateScheduler {
+        LearningRateScheduler { schedule }
+    }
+}
+
+impl Callback for LearningRateScheduler {
+    fn on_epoch_begin(&mut self, model: &mut dyn Model, epoch: usize, _logs: &Logs) {
+        let learning_rate = (self.schedule)(epoch, model.learning_rate());
+        model.set_learning_rate(learning_rate);
+    }
+}
+
+// Writes the epoch logs to a CSV file, one row per epoch. The columns are taken from the first
+// epoch.
+pub struct CSVLogger {
+    pub path: String,
+    file: Option<File>,
+    columns: Vec<String>,
+}
+
+impl CSVLogger {
+    pub fn new(path: &str) -> CSVLogger {
+        CSVLogger {
+            path: path.to_string(),
+            file: None,
+            columns: vec![],
+        }
+    }
+
+    fn write_line(&mut self, line: String) {
+        let file = self.file.as_mut().unwrap();
+        if let Err(error) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            panic!("Could not write to {}: {}", self.path, error);
+        }
+    }
+}
+
+impl Callback for CSVLogger {
+    fn on_train_begin(&mut self, _model: &mut dyn Model, _logs: &Logs) {
+        match File::create(&self.path) {
+            Ok(file) => self.file = Some(file),
+            Err(error) => panic!("Could not create {}: {}", self.path, error),
+        }
+        self.columns.clear();
+    }
+
+    fn on_epoch_end(&mut self, _model: &mut dyn Model, epoch: usize, logs: &Logs) {
+        if self.columns.is_empty() {
+            self.columns = logs.keys().cloned().collect();
+            let header = format!("epoch,{}", self.columns.join(","));
+            self.write_line(header);
+        }
+
+        let mut row = vec![(epoch + 1).to_string()];
+        row.extend(self.columns.iter().map(|column| match logs.get(column) {
+            Some(value) => value.to_string(),
+            None => String::new(),
+        }));
+        self.write_line(row.join(","));
+    }
+
+    fn on_train_end(&mut self, _model: &mut dyn Model, _logs: &Logs) {
+        self.file = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        create_tensor_context,
+        graph::{
+            graph::{Sequential, Validation},
+            history::History,
+            loss_function::LossFunction,
+            optimizer::SGD,
+        },
+        layers::{dense::Dense, input::Input, layers::layers::Layer},
+        math::tensor::Tensor,
+        nuerons::activation_function::ActivationFunction,
+    };
+
+    use super::*;
+
+    fn network() -> Sequential {
+        let tensor_context = create_tensor_context!(1024);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Input::new(tensor_context.clone(), vec![1])),
+            Box::new(Dense::new(tensor_context.clone(), 1, ActivationFunction::Tanh)),
+        ];
+        let mut network = Sequential::new(tensor_context, layers);
+        network.compile(
+            vec![1],
+            vec![1],
+            Box::new(SGD::new(0.1)),
+            LossFunction::MeanSquaredError,
+            vec![],
+        );
+        network
+    }
+
+    fn fit(network: &mut Sequential, epochs: usize, callbacks: &mut [&mut dyn Callback]) -> History {
+        network.fit(
+            Tensor::new(vec![4], vec![0.0, 0.25, 0.5, 0.75]),
+            Tensor::new(vec![4], vec![0.0, 0.1, 0.2, 0.3]),
+            epochs,
+            2,
+            Validation::None,
+            callbacks,
+        )
+    }
+
+    fn logs(loss: f64) -> Logs {
+        Logs::from([("loss".to_string(), loss)])
+    }
+
+    #[test]
+    fn test_mode_for_metric() {
+        assert_eq!(Mode::for_metric("val_loss"), Mode::Min);
+        assert_eq!(Mode::for_metric("mae"), Mode::Min);
+        assert_eq!(Mode::for_metric("val_accuracy"), Mode::Max);
+        assert_eq!(Mode::for_metric("f1_macro"), Mode::Max);
+    }
+
+    #[test]
+    fn test_early_stopping_restores_best_weights() {
+        let mut network = network();
+        let best_weights = network.get_weights();
+        let mut early_stopping = EarlyStopping::new("loss", 2, true);
+        early_stopping.on_train_begin(&mut network, &Logs::new());
+        early_stopping.on_epoch_end(&mut network, 0, &logs(1.0));
+
+        let worse_weights: Vec<Vec<f64>> = best_weights
+            .iter()
+            .map(|weights| weights.iter().map(|weight| weight + 1.0).collect())
+            .collect();
+        network.set_weights(worse_weights.clone());
+        early_stopping.on_epoch_end(&mut network, 1, &logs(1.5));
+        assert_eq!(early_stopping.stopped_epoch, None);
+        early_stopping.on_epoch_end(&mut network, 2, &logs(1.0));
+        assert_eq!(early_stopping.stopped_epoch, Some(2));
+        assert_eq!(network.get_weights(), worse_weights);
+
+        early_stopping.on_train_end(&mut network, &logs(1.0));
+        assert_eq!(network.get_weights(), best_weights);
+    }
+
+    #[test]
+    fn test_early_stopping_ends_fit() {
+        let mut network = network();
+        let mut early_stopping = EarlyStopping::new("loss", 2, false);
+        // Nothing after the first epoch counts as an improvement
+        early_stopping.min_delta = 1e9;
+        let history = fit(&mut network, 10, &mut [&mut early_stopping]);
+        assert_eq!(history.epochs(), 3);
+        assert_eq!(early_stopping.stopped_epoch, Some(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot monitor val_loss")]
+    fn test_early_stopping_missing_monitor() {
+        let mut network = network();
+        let mut early_stopping = EarlyStopping::new("val_loss", 2, false);
+        fit(&mut network, 1, &mut [&mut early_stopping]);
+    }
+
+    #[test]
+    fn test_model_checkpoint() {
+        let path = std::env::temp_dir().join("callbacks_test_model_checkpoint_{epoch}.json");
+        let path = path.to_str().unwrap();
+        let mut network = network();
+        let mut checkpoint = ModelCheckpoint::new(path, "loss", false);
+        fit(&mut network, 2, &mut [&mut checkpoint]);
+
+        let first = path.replace("{epoch}", "1");
+        let last = path.replace("{epoch}", "2");
+        assert_eq!(load_weights(&last), Ok(network.get_weights()));
+        assert_ne!(load_weights(&first), load_weights(&last));
+        fs::remove_file(first).unwrap();
+        fs::remove_file(last).unwrap();
+    }
+
+    #[test]
+    fn test_learning_rate_scheduler() {
+        let mut network = network();
+        let mut scheduler = LearningRateScheduler::new(Box::new(|_epoch, learning_rate| learning_rate / 2.0));
+        let history = fit(&mut network, 3, &mut [&mut scheduler]);
+        assert_eq!(history.get("lr"), Some(&[0.05, 0.025, 0.0125][..]));
+    }
+
+    #[test]
+    fn test_csv_logger() {
+        let path = std::env::temp_dir().join("callbacks_test_csv_logger.csv");
+        let path = path.to_str().unwrap();
+        let mut network = network();
+        let mut logger = CSVLogger::new(path);
+        let history = fit(&mut network, 3, &mut [&mut logger]);
+
+        let contents = fs::read_to_string(path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "epoch,loss,lr");
+        let losses = history.get("loss").unwrap();
+        assert_eq!(lines[3], format!("3,{},0.1", losses[2]));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,21 +1,47 @@
-use std::{cell::RefCell, iter, rc::Rc};
+use std::{cell::RefCell, ops::Range, rc::Rc};
+
+use rand::seq::SliceRandom;
 
 use crate::{
     layers::layers::layers::Layer,
     math::{
         tensor::Tensor,
-        tensor_context::{self, TensorContext, TensorRef},
+        tensor_context::{TensorContext, TensorRef},
     },
 };
 
-use super::{loss_function::LossFunction, network_metric::Metric, optimizer::Optimizer};
+use super::{
+    callbacks::{Callback, Logs},
+    history::History,
+    learning_rate_schedule::LearningRateSchedule,
+    loss_function::LossFunction,
+    network_metric::{Metric, MetricAccumulator},
+    optimizer::{Optimizer, SGD},
+};
 
 pub struct Sequential {
     pub layers: std::vec::Vec<Box<dyn Layer>>,
     pub context: Rc<RefCell<TensorContext>>,
     output_value: Option<TensorRef>,
     loss_function: LossFunction,
+    metrics: Vec<Metric>,
+    optimizer: Box<dyn Optimizer>,
+    // Overrides the optimizer's learning rate before every step when set
+    learning_rate_schedule: Option<Box<dyn LearningRateSchedule>>,
     parameters: Vec<TensorRef>,
+    input_shape: Vec<usize>,
+    // Shape of the labels for one sample, which depends on the loss function
+    label_shape: Vec<usize>,
+    // Placeholders fed with one batch at a time, with the batch along the leading axis
+    input_tensor: Option<TensorRef>,
+    label_tensor: Option<TensorRef>,
+    loss_value: Option<TensorRef>,
+    // Tensors recorded for the loss, recomputed after every forward pass
+    loss_tensors: Range<TensorRef>,
+    // Set by callbacks to end fit after the current epoch
+    stop_training: bool,
+    // Whether layers behave as in training or as in inference
+    training: bool,
 }
 
 impl Sequential {
@@ -25,8 +51,104 @@ impl Sequential {
             context,
             output_value: None,
             loss_function: LossFunction::MeanSquaredError,
+            metrics: vec![],
+            optimizer: Box::new(SGD::default()),
+            learning_rate_schedule: None,
             parameters: vec![],
+            input_shape: vec![],
+            label_shape: vec![],
+            input_tensor: None,
+            label_tensor: None,
+            loss_value: None,
+            loss_tensors: 0..0,
+            stop_training: false,
+            training: false,
+        }
+    }
+
+    pub fn set_learning_rate_schedule(&mut self, schedule: Box<dyn LearningRateSchedule>) {
+        self.learning_rate_schedule = Some(schedule);
+    }
+
+    fn metric_accumulators(&self) -> Vec<Box<dyn MetricAccumulator>> {
+        self.metrics.iter().map(|metric| metric.accumulator()).collect()
+    }
+
+    // Number of samples in a dataset, checking that there are labels for exactly that many
+    fn sample_count(&self, data: &[f64], labels: &[f64]) -> usize {
+        let sample_count = data.len() / self.input_shape.iter().product::<usize>();
+        let label_count = labels.len() / self.label_shape.iter().product::<usize>();
+        if label_count != sample_count {
+            panic!("Got {} samples but {} labels", sample_count, label_count);
         }
+        sample_count
+    }
+
+    // Runs the network forward over a dataset one batch at a time, accumulating the loss and
+    // every metric
+    fn evaluate_dataset(&mut self, data: &[f64], labels: &[f64], batch_size: usize) -> Evaluation {
+        let input_size = self.input_shape.iter().product::<usize>();
+        let label_size = self.label_shape.iter().product::<usize>();
+        let sample_count = self.sample_count(data, labels);
+
+        let mut loss = 0.0;
+        let mut accumulators = self.metric_accumulators();
+        for start in (0..sample_count).step_by(batch_size) {
+            let end = (start + batch_size).min(sample_count);
+            let batch_labels = labels[start * label_size..end * label_size].to_vec();
+            let output = self.forward_batch(data[start * input_size..end * input_size].to_vec());
+            let predictions = self.context.borrow().get_tensor(output).data;
+            for accumulator in accumulators.iter_mut() {
+                accumulator.update(&predictions, &batch_labels, end - start);
+            }
+            let loss_value = self.compute_loss(batch_labels);
+            loss += self.context.borrow().get_tensor(loss_value).data[0] * (end - start) as f64;
+        }
+
+        Evaluation {
+            loss: loss / sample_count.max(1) as f64,
+            metrics: self
+                .metrics
+                .iter()
+                .zip(accumulators.iter())
+                .map(|(metric, accumulator)| (metric.name(), accumulator.result()))
+                .collect(),
+        }
+    }
+
+    // Loads a batch into the input placeholder and runs every layer over it
+    fn forward_batch(&mut self, data: Vec<f64>) -> TensorRef {
+        let input_tensor = self.input_tensor.unwrap();
+        let sample_size = self.input_shape.iter().product::<usize>();
+        let mut shape = vec![data.len() / sample_size];
+        shape.extend(self.input_shape.iter());
+        {
+            let mut context = self.context.borrow_mut();
+            context.set_shape(input_tensor, shape);
+            context.set_data(input_tensor, data);
+        }
+
+        let mut previous_layer_output = input_tensor;
+        for layer in self.layers.iter() {
+            previous_layer_output = layer.forward(previous_layer_output);
+        }
+        previous_layer_output
+    }
+
+    // Loads the labels for the batch currently in the network and recomputes the loss
+    fn compute_loss(&mut self, labels: Vec<f64>) -> TensorRef {
+        let label_tensor = self.label_tensor.unwrap();
+        let sample_size = self.label_shape.iter().product::<usize>();
+        let mut shape = vec![labels.len() / sample_size];
+        shape.extend(self.label_shape.iter());
+
+        let mut context = self.context.borrow_mut();
+        context.set_shape(label_tensor, shape);
+        context.set_data(label_tensor, labels);
+        for tensor in self.loss_tensors.clone() {
+            context.recompute(tensor);
+        }
+        self.loss_value.unwrap()
     }
 }
 
@@ -34,119 +156,310 @@ impl Model for Sequential {
     fn compile(
         &mut self,
         input_shape: Vec<usize>,
-        _output_shape: Vec<usize>,
-        _optimizer: Optimizer,
+        output_shape: Vec<usize>,
+        optimizer: Box<dyn Optimizer>,
         loss: LossFunction,
-        _metrics: Vec<Metric>,
+        metrics: Vec<Metric>,
     ) {
+        self.label_shape = loss.label_shape(&output_shape);
         self.loss_function = loss;
+        self.metrics = metrics;
+        self.optimizer = optimizer;
+        self.input_shape = input_shape.clone();
 
-        // Iterate thropugh all layers and compile them
-        let dummy = self
+        // The graph is built once over a batch of one and resized as batches are fed through it
+        let mut batch_input_shape = vec![1];
+        batch_input_shape.extend(input_shape.iter());
+        let input = self
             .context
             .borrow_mut()
-            .new_tensor(input_shape.clone(), vec![0.0; input_shape.iter().product()]);
-        let mut last_layer = Some(dummy);
+            .new_tensor(batch_input_shape, vec![0.0; input_shape.iter().product()]);
+        self.input_tensor = Some(input);
+
+        // Iterate thropugh all layers and compile them
+        let mut last_layer = Some(input);
         for layer in self.layers.iter_mut() {
             last_layer = Some(layer.compile(last_layer.unwrap()));
             println!("Compiled Layer: {:?}", last_layer);
         }
         self.output_value = last_layer;
 
-        self.parameters = self.layers.iter().map(|layer| layer.get_parameters()).flatten().collect();
-    }
-
-    fn fit(&mut self, data: Tensor, labels: Tensor, epochs: usize) {
-        let data_tensor = self.context.borrow_mut().transfer_tensor(data);
-        let labels_tensor = self.context.borrow_mut().transfer_tensor(labels);
-
-        // Split tensor into input tensors, assume that first dimension is always the input count
-        let data = self.context.borrow_mut().get_tensor(data_tensor);
-        let labels = self.context.borrow_mut().get_tensor(labels_tensor);
-
-        let data_shape = data.shape;
-        let input_count = data_shape[0];
-        let input_shape = data_shape[1..].to_vec();
-        let input_shape_product = input_shape.iter().product::<usize>();
-
-        let input_tensors: Vec<TensorRef> = (0..input_count)
-            .map(|i| {
-                let start = i * input_shape_product;
-                let end = start + input_shape_product;
-                self.context
-                    .borrow_mut()
-                    .new_tensor(input_shape.clone(), data.data[start..end].to_vec())
-            })
-            .collect();
-
-        let labels_shape = labels.shape[1..].to_vec();
-        let labels_shape_product = labels_shape.iter().product::<usize>();
-        let label_tensors: Vec<TensorRef> = (0..input_count)
-            .map(|i| {
-                let start = i * labels_shape_product;
-                let end = start + labels_shape_product;
-                self.context
-                    .borrow_mut()
-                    .new_tensor(labels_shape.clone(), labels.data[start..end].to_vec())
-            })
-            .collect();
-
-        let pair: Vec<(&TensorRef, &TensorRef)> =
-            input_tensors.iter().zip(label_tensors.iter()).collect();
-
-        for _ in 0..epochs {
-            let losses: Vec<TensorRef> = pair
-                .iter()
-                .enumerate()
-                .map(|(i, (input, label))| {
-                    let prediction = self.predict_tensor(**input);
-                    let loss = self.loss_function.loss(self.context.clone(), prediction, **label);
-                    loss
-                })
-                .collect();
-            let full_loss_vector = self.context.borrow_mut().concat(losses);
-            let full_loss = self.context.borrow_mut().sum(full_loss_vector);
+        let mut label_shape = vec![1];
+        label_shape.extend(self.label_shape.iter());
+        let label = self
+            .context
+            .borrow_mut()
+            .new_tensor(label_shape, vec![0.0; self.label_shape.iter().product()]);
+        self.label_tensor = Some(label);
+
+        let loss_start = self.context.borrow().tensor_count();
+        let loss = self
+            .loss_function
+            .loss(self.context.clone(), self.output_value.unwrap(), label);
+        self.loss_value = Some(loss);
+        self.loss_tensors = loss_start..loss + 1;
 
-            self.context.borrow_mut().backwards(full_loss);
+        self.parameters = self.layers.iter().flat_map(|layer| layer.get_parameters()).collect();
+    }
 
-            self.parameters.iter().for_each(|parameter| {
-                self.context.borrow_mut().update_data_from_grad(*parameter, -0.1);
-            });
+    fn fit(
+        &mut self,
+        data: Tensor,
+        labels: Tensor,
+        epochs: usize,
+        batch_size: usize,
+        validation: Validation,
+        callbacks: &mut [&mut dyn Callback],
+    ) -> History {
+        if batch_size == 0 {
+            panic!("Batch size must be at least 1");
         }
+        let input_size = self.input_shape.iter().product::<usize>();
+        let label_size = self.label_shape.iter().product::<usize>();
+        let sample_count = self.sample_count(&data.data, &labels.data);
+
+        let mut order: Vec<usize> = (0..sample_count).collect();
+        let validation_data = match validation {
+            Validation::None => None,
+            Validation::Split { fraction, shuffle } => {
+                if fraction <= 0.0 || fraction >= 1.0 {
+                    panic!("Validation split must be between 0 and 1, got {}", fraction);
+                }
+                if shuffle {
+                    order.shuffle(&mut rand::thread_rng());
+                }
+                // At least one sample is held out, so the validation loss is never taken over nothing
+                let held_out_count = ((sample_count as f64 * fraction).round() as usize).max(1);
+                let held_out = order.split_off(sample_count.saturating_sub(held_out_count));
+                Some((
+                    gather(&data.data, &held_out, input_size),
+                    gather(&labels.data, &held_out, label_size),
+                ))
+            }
+            Validation::Data(data, labels) => {
+                self.sample_count(&data.data, &labels.data);
+                Some((data.data, labels.data))
+            }
+        };
+        if order.is_empty() {
+            panic!("No samples left to train on");
+        }
+
+        let mut history = History::new();
+        let mut logs = Logs::new();
+        self.stop_training = false;
+        for callback in callbacks.iter_mut() {
+            callback.on_train_begin(self, &logs);
+        }
+
+        for epoch in 0..epochs {
+            for callback in callbacks.iter_mut() {
+                callback.on_epoch_begin(self, epoch, &Logs::new());
+            }
+
+            self.set_training(true);
+            order.shuffle(&mut rand::thread_rng());
+            let mut epoch_loss = 0.0;
+            let mut accumulators = self.metric_accumulators();
+
+            for (batch_index, batch) in order.chunks(batch_size).enumerate() {
+                let mut batch_logs = Logs::from([("size".to_string(), batch.len() as f64)]);
+                for callback in callbacks.iter_mut() {
+                    callback.on_batch_begin(self, batch_index, &batch_logs);
+                }
+
+                let batch_data = gather(&data.data, batch, input_size);
+                let batch_labels = gather(&labels.data, batch, label_size);
+
+                let output = self.forward_batch(batch_data);
+                let predictions = self.context.borrow().get_tensor(output).data;
+                for accumulator in accumulators.iter_mut() {
+                    accumulator.update(&predictions, &batch_labels, batch.len());
+                }
+                let loss = self.compute_loss(batch_labels);
+
+                if let Some(schedule) = &self.learning_rate_schedule {
+                    self.optimizer.set_learning_rate(schedule.learning_rate());
+                }
+
+                let mut context = self.context.borrow_mut();
+                let batch_loss = context.get_tensor(loss).data[0];
+                epoch_loss += batch_loss * batch.len() as f64;
+                context.backwards(loss);
+                self.optimizer.step(&mut context, &self.parameters);
+                context.reset_grads(loss);
+                drop(context);
+
+                if let Some(schedule) = &mut self.learning_rate_schedule {
+                    schedule.on_step_end();
+                }
+
+                batch_logs.insert("loss".to_string(), batch_loss);
+                for callback in callbacks.iter_mut() {
+                    callback.on_batch_end(self, batch_index, &batch_logs);
+                }
+            }
+
+            epoch_loss /= order.len() as f64;
+            logs = Logs::new();
+            logs.insert("loss".to_string(), epoch_loss);
+            let mut log = format!("Epoch {}/{} - loss: {:.6}", epoch + 1, epochs, epoch_loss);
+            for (metric, accumulator) in self.metrics.iter().zip(accumulators.iter()) {
+                logs.insert(metric.name(), accumulator.result());
+                log.push_str(&format!(" - {}: {:.6}", metric.name(), accumulator.result()));
+            }
+
+            // Schedules watch the validation loss when there is one
+            let mut monitored_loss = epoch_loss;
+            if let Some((validation_data, validation_labels)) = &validation_data {
+                self.set_training(false);
+                let evaluation = self.evaluate_dataset(validation_data, validation_labels, batch_size);
+                monitored_loss = evaluation.loss;
+                logs.insert("val_loss".to_string(), evaluation.loss);
+                log.push_str(&format!(" - val_loss: {:.6}", evaluation.loss));
+                for (name, value) in evaluation.metrics.iter() {
+                    logs.insert(format!("val_{}", name), *value);
+                    log.push_str(&format!(" - val_{}: {:.6}", name, value));
+                }
+            }
+
+            // The learning rate reported is the one used for the last step of the epoch
+            let learning_rate = self.optimizer.learning_rate();
+            if let Some(schedule) = &mut self.learning_rate_schedule {
+                schedule.on_epoch_end(monitored_loss);
+            }
+            logs.insert("lr".to_string(), learning_rate);
+            println!("{} - lr: {:.6}", log, learning_rate);
+
+            for (name, value) in logs.iter() {
+                history.record(name, *value);
+            }
+            for callback in callbacks.iter_mut() {
+                callback.on_epoch_end(self, epoch, &logs);
+            }
+            if self.stop_training {
+                break;
+            }
+        }
+
+        self.set_training(false);
+        for callback in callbacks.iter_mut() {
+            callback.on_train_end(self, &logs);
+        }
+        history
     }
+
+    // Runs a tensor holding one sample, or a batch of samples along its leading axis, through the
+    // network and returns the output tensor
     fn predict_tensor(&mut self, data: TensorRef) -> TensorRef {
-        let mut previous_layer_output = data;
-        for layer in self.layers.iter() {
-            previous_layer_output = layer.forward(previous_layer_output);
-        }
-        previous_layer_output
+        self.set_training(false);
+        let data = self.context.borrow().get_tensor(data).data;
+        self.forward_batch(data)
     }
 
     fn predict(&mut self, data: Vec<f64>) -> Vec<f64> {
-        println!("Input: {:?}", data.clone().iter().fold(0, |acc, x| acc + x.round() as i32));
-
-        let input = self.context.borrow_mut().new_tensor(vec![data.len()], data);
-        println!("Input Tensor: {:?}", input);
-
+        self.set_training(false);
+        let output = self.forward_batch(data);
+        self.context.borrow().get_tensor(output).data
+    }
 
-        let mut previous_layer_output = input;
-        for layer in self.layers.iter() {
-            previous_layer_output = layer.forward(previous_layer_output);
-            println!("Layer Output: {:?}", self.context.borrow_mut().get_tensor(previous_layer_output).data.iter().fold(0, |acc, x| acc + x.round() as i32));
+    fn evaluate(&mut self, data: Tensor, labels: Tensor, batch_size: usize) -> Evaluation {
+        if batch_size == 0 {
+            panic!("Batch size must be at least 1");
         }
+        self.set_training(false);
+        self.evaluate_dataset(&data.data, &labels.data, batch_size)
+    }
 
-        let final_output = previous_layer_output;
-        println!("Output Tensor: {:?}", final_output);
-        self.context.borrow_mut().get_tensor(final_output).data
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+        for layer in self.layers.iter_mut() {
+            layer.set_training(training);
+        }
     }
 
-    fn evaluate(&self, _data: Vec<f64>, _labels: Vec<f64>) -> (f64, f64) {
-        todo!()
+    fn is_training(&self) -> bool {
+        self.training
     }
 
     fn save(&self) {
         todo!()
     }
+
+    fn get_weights(&self) -> Vec<Vec<f64>> {
+        let context = self.context.borrow();
+        self.parameters
+            .iter()
+            .map(|parameter| context.get_tensor(*parameter).data)
+            .collect()
+    }
+
+    fn set_weights(&mut self, weights: Vec<Vec<f64>>) {
+        if weights.len() != self.parameters.len() {
+            panic!(
+                "Got weights for {} parameters but the model has {}",
+                weights.len(),
+                self.parameters.len()
+            );
+        }
+        let mut context = self.context.borrow_mut();
+        for (parameter, data) in self.parameters.iter().zip(weights) {
+            let size = context.get_tensor(*parameter).data.len();
+            if data.len() != size {
+                panic!("Got {} weights for a parameter of size {}", data.len(), size);
+            }
+            context.set_data(*parameter, data);
+        }
+    }
+
+    fn stop_training(&mut self) {
+        self.stop_training = true;
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.optimizer.learning_rate()
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.optimizer.set_learning_rate(learning_rate);
+    }
+}
+
+// Copies the given samples of a dataset into one buffer
+fn gather(data: &[f64], samples: &[usize], sample_size: usize) -> Vec<f64> {
+    let mut gathered = Vec::with_capacity(samples.len() * sample_size);
+    for sample in samples {
+        gathered.extend(&data[sample * sample_size..(sample + 1) * sample_size]);
+    }
+    gathered
+}
+
+// Data held out from training and evaluated at the end of every epoch. Only one is built per call
+// to fit, so the size of the Data variant does not matter.
+#[allow(clippy::large_enum_variant)]
+pub enum Validation {
+    None,
+    // Holds out a fraction of the samples, taken from the end of the dataset or, when shuffling,
+    // from a random permutation of it
+    Split { fraction: f64, shuffle: bool },
+    // Separate samples and labels
+    Data(Tensor, Tensor),
+}
+
+// Mean loss over a dataset and the value of every metric the model was compiled with
+#[derive(Debug, Clone, PartialEq)]
+pub struct Evaluation {
+    pub loss: f64,
+    pub metrics: Vec<(String, f64)>,
+}
+
+impl Evaluation {
+    pub fn metric(&self, name: &str) -> Option<f64> {
+        self.metrics
+            .iter()
+            .find(|(metric, _)| metric == name)
+            .map(|(_, value)| *value)
+    }
 }
 
 pub trait Model {
@@ -154,13 +467,351 @@ pub trait Model {
         &mut self,
         input_shape: Vec<usize>,
         output_shape: Vec<usize>,
-        optimizer: Optimizer,
+        optimizer: Box<dyn Optimizer>,
         loss: LossFunction,
         metrics: Vec<Metric>,
     );
-    fn fit(&mut self, data: Tensor, labels: Tensor, epochs: usize);
+    fn fit(
+        &mut self,
+        data: Tensor,
+        labels: Tensor,
+        epochs: usize,
+        batch_size: usize,
+        validation: Validation,
+        callbacks: &mut [&mut dyn Callback],
+    ) -> History;
     fn predict(&mut self, data: Vec<f64>) -> Vec<f64>;
     fn predict_tensor(&mut self, data: TensorRef) -> TensorRef;
-    fn evaluate(&self, data: Vec<f64>, labels: Vec<f64>) -> (f64, f64);
+    // Runs the network forward over a dataset without training it
+    fn evaluate(&mut self, data: Tensor, labels: Tensor, batch_size: usize) -> Evaluation;
+    // Switches every layer between training and inference behaviour. fit turns training on, and
+    // evaluate and predict turn it off.
+    fn set_training(&mut self, training: bool);
+    fn is_training(&self) -> bool;
     fn save(&self);
+    // Data of every trainable parameter, in the order the layers create them
+    fn get_weights(&self) -> Vec<Vec<f64>>;
+    fn set_weights(&mut self, weights: Vec<Vec<f64>>);
+    // Ends fit after the current epoch, for callbacks
+    fn stop_training(&mut self);
+    fn learning_rate(&self) -> f64;
+    fn set_learning_rate(&mut self, learning_rate: f64);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        create_tensor_context,
+        graph::{
+            learning_rate_schedule::{ScheduleInterval, StepDecay},
+            network_metric::Average,
+        },
+        layers::{dense::Dense, dropout::Dropout, input::Input},
+        nuerons::activation_function::ActivationFunction,
+    };
+
+    use super::*;
+
+    fn mean_squared_error(network: &mut Sequential, data: &[f64], labels: &[f64]) -> f64 {
+        let predictions = network.predict(data.to_vec());
+        predictions
+            .iter()
+            .zip(labels.iter())
+            .map(|(prediction, label)| (prediction - label).powi(2))
+            .sum::<f64>()
+            / labels.len() as f64
+    }
+
+    fn linear_network() -> Sequential {
+        let tensor_context = create_tensor_context!(1024);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Input::new(tensor_context.clone(), vec![1])),
+            Box::new(Dense::new(tensor_context.clone(), 1, ActivationFunction::Tanh)),
+        ];
+        let mut network = Sequential::new(tensor_context, layers);
+        network.compile(
+            vec![1],
+            vec![1],
+            Box::new(SGD::new(0.1)),
+            LossFunction::MeanSquaredError,
+            vec![],
+        );
+        network
+    }
+
+    #[test]
+    fn test_predict_batch() {
+        let mut network = linear_network();
+        let predictions = network.predict(vec![0.1, 0.2, 0.3]);
+        assert_eq!(predictions.len(), 3);
+
+        let single = network.predict(vec![0.2]);
+        assert_eq!(single[0], predictions[1]);
+    }
+
+    #[test]
+    fn test_fit_reduces_loss() {
+        let mut network = linear_network();
+        let data: Vec<f64> = (0..64).map(|i| i as f64 / 64.0).collect();
+        let labels: Vec<f64> = data.iter().map(|x| 0.5 * x - 0.2).collect();
+        let initial_loss = mean_squared_error(&mut network, &data, &labels);
+
+        let history = network.fit(
+            Tensor::new(vec![64], data.clone()),
+            Tensor::new(vec![64], labels.clone()),
+            50,
+            8,
+            Validation::None,
+            &mut [],
+        );
+
+        let final_loss = mean_squared_error(&mut network, &data, &labels);
+        assert!(final_loss < initial_loss);
+        let losses = history.get("loss").unwrap();
+        assert_eq!(losses.len(), 50);
+        assert!(losses[49] < losses[0]);
+        assert!(final_loss < 0.01, "final loss {}", final_loss);
+    }
+
+    #[test]
+    fn test_fit_follows_learning_rate_schedule() {
+        let mut network = linear_network();
+        network.set_learning_rate_schedule(Box::new(StepDecay::new(
+            0.1,
+            0.5,
+            1,
+            ScheduleInterval::Epoch,
+        )));
+        let history = network.fit(
+            Tensor::new(vec![4], vec![0.0, 0.25, 0.5, 0.75]),
+            Tensor::new(vec![4], vec![0.0, 0.1, 0.2, 0.3]),
+            3,
+            2,
+            Validation::None,
+            &mut [],
+        );
+
+        // The third epoch runs at 0.1 * 0.5^2
+        assert!((network.optimizer.learning_rate() - 0.025).abs() < 1e-12);
+        assert_eq!(history.get("lr"), Some(&[0.1, 0.05, 0.025][..]));
+    }
+
+    #[test]
+    fn test_fit_sparse_cross_entropy() {
+        let tensor_context = create_tensor_context!(1024);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Input::new(tensor_context.clone(), vec![1])),
+            Box::new(Dense::new(tensor_context.clone(), 2, ActivationFunction::Tanh)),
+        ];
+        let mut network = Sequential::new(tensor_context, layers);
+        network.compile(
+            vec![1],
+            vec![2],
+            Box::new(SGD::new(0.5)),
+            LossFunction::SparseCrossEntropy { from_logits: true },
+            vec![],
+        );
+
+        // Class 1 for positive inputs, class 0 otherwise
+        let data: Vec<f64> = (0..32).map(|i| i as f64 / 16.0 - 1.0).collect();
+        let labels: Vec<f64> = data.iter().map(|x| if *x > 0.0 { 1.0 } else { 0.0 }).collect();
+        network.fit(
+            Tensor::new(vec![32], data.clone()),
+            Tensor::new(vec![32], labels.clone()),
+            100,
+            8,
+            Validation::None,
+            &mut [],
+        );
+
+        let predictions = network.predict(data);
+        let correct = predictions
+            .chunks(2)
+            .zip(labels.iter())
+            .filter(|(scores, label)| (scores[1] > scores[0]) == (**label == 1.0))
+            .count();
+        assert!(correct >= 30, "{} of 32 correct", correct);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let tensor_context = create_tensor_context!(1024);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Input::new(tensor_context.clone(), vec![1])),
+            Box::new(Dense::new(tensor_context.clone(), 1, ActivationFunction::Sigmoid)),
+        ];
+        let mut network = Sequential::new(tensor_context.clone(), layers);
+        network.compile(
+            vec![1],
+            vec![1],
+            Box::new(SGD::new(0.1)),
+            LossFunction::MeanSquaredError,
+            vec![Metric::Accuracy, Metric::Precision(Average::Macro)],
+        );
+        // Output is sigmoid(x), so positive inputs are predicted as the positive class
+        let parameters = network.parameters.clone();
+        tensor_context.borrow_mut().set_data(parameters[0], vec![1.0]);
+
+        let data = Tensor::new(vec![4], vec![-2.0, -1.0, 1.0, 2.0]);
+        let labels = Tensor::new(vec![4], vec![0.0, 1.0, 1.0, 1.0]);
+        let evaluation = network.evaluate(data, labels, 3);
+
+        let sigmoid = |x: f64| 1.0 / (1.0 + (-x).exp());
+        let expected_loss = (sigmoid(-2.0).powi(2)
+            + (sigmoid(-1.0) - 1.0).powi(2)
+            + (sigmoid(1.0) - 1.0).powi(2)
+            + (sigmoid(2.0) - 1.0).powi(2))
+            / 4.0;
+        assert!((evaluation.loss - expected_loss).abs() < 1e-12);
+        assert_eq!(evaluation.metric("accuracy"), Some(0.75));
+        assert_eq!(evaluation.metric("precision_macro"), Some(1.0));
+    }
+
+    #[test]
+    fn test_fit_validation_split() {
+        let mut network = linear_network();
+        let data: Vec<f64> = (0..10).map(|i| i as f64 / 10.0).collect();
+        let labels: Vec<f64> = data.iter().map(|x| 0.5 * x).collect();
+        let history = network.fit(
+            Tensor::new(vec![10], data.clone()),
+            Tensor::new(vec![10], labels.clone()),
+            3,
+            4,
+            Validation::Split {
+                fraction: 0.2,
+                shuffle: false,
+            },
+            &mut [],
+        );
+
+        // Without shuffling the last two samples are held out
+        let validation_losses = history.get("val_loss").unwrap();
+        assert_eq!(validation_losses.len(), 3);
+        let evaluation = network.evaluate(
+            Tensor::new(vec![2], data[8..].to_vec()),
+            Tensor::new(vec![2], labels[8..].to_vec()),
+            4,
+        );
+        assert_eq!(validation_losses[2], evaluation.loss);
+    }
+
+    #[test]
+    fn test_fit_tiny_validation_split() {
+        let mut network = linear_network();
+        let data = vec![0.0, 0.25, 0.5, 0.75];
+        let labels = vec![0.2, 0.3, 0.4, 0.5];
+        let history = network.fit(
+            Tensor::new(vec![4], data.clone()),
+            Tensor::new(vec![4], labels.clone()),
+            1,
+            4,
+            Validation::Split {
+                fraction: 0.01,
+                shuffle: false,
+            },
+            &mut [],
+        );
+
+        // A split that rounds to no samples still holds out the last one
+        let evaluation = network.evaluate(
+            Tensor::new(vec![1], data[3..].to_vec()),
+            Tensor::new(vec![1], labels[3..].to_vec()),
+            4,
+        );
+        assert_eq!(history.get("val_loss").unwrap(), &[evaluation.loss]);
+        assert!(evaluation.loss > 0.0);
+    }
+
+    #[test]
+    fn test_fit_validation_data() {
+        let tensor_context = create_tensor_context!(1024);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Input::new(tensor_context.clone(), vec![1])),
+            Box::new(Dense::new(tensor_context.clone(), 1, ActivationFunction::Tanh)),
+        ];
+        let mut network = Sequential::new(tensor_context, layers);
+        network.compile(
+            vec![1],
+            vec![1],
+            Box::new(SGD::new(0.1)),
+            LossFunction::MeanSquaredError,
+            vec![Metric::MeanAbsoluteError],
+        );
+        let history = network.fit(
+            Tensor::new(vec![4], vec![0.0, 0.25, 0.5, 0.75]),
+            Tensor::new(vec![4], vec![0.0, 0.1, 0.2, 0.3]),
+            2,
+            2,
+            Validation::Data(Tensor::new(vec![1], vec![1.0]), Tensor::new(vec![1], vec![0.4])),
+            &mut [],
+        );
+        assert_eq!(history.names(), vec!["loss", "lr", "mae", "val_loss", "val_mae"]);
+        assert_eq!(history.get("val_mae").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_training_mode() {
+        let tensor_context = create_tensor_context!(1024);
+        let layers: Vec<Box<dyn Layer>> = vec![
+            Box::new(Input::new(tensor_context.clone(), vec![8])),
+            Box::new(Dropout::new(tensor_context.clone(), 0.5)),
+        ];
+        let mut network = Sequential::new(tensor_context, layers);
+        network.compile(vec![8], vec![8], Box::new(SGD::new(0.1)), LossFunction::MeanSquaredError, vec![]);
+        let data: Vec<f64> = (1..=8).map(|i| i as f64).collect();
+
+        network.set_training(true);
+        assert!(network.is_training());
+        // predict switches dropout off
+        assert_eq!(network.predict(data.clone()), data);
+        assert!(!network.is_training());
+
+        network.fit(
+            Tensor::new(vec![1, 8], data.clone()),
+            Tensor::new(vec![1, 8], data.clone()),
+            1,
+            1,
+            Validation::None,
+            &mut [],
+        );
+        assert!(!network.is_training());
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch size must be at least 1")]
+    fn test_fit_zero_batch_size() {
+        let mut network = linear_network();
+        network.fit(
+            Tensor::new(vec![2], vec![0.0, 1.0]),
+            Tensor::new(vec![2], vec![0.0, 1.0]),
+            1,
+            0,
+            Validation::None,
+            &mut [],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch size must be at least 1")]
+    fn test_evaluate_zero_batch_size() {
+        let mut network = linear_network();
+        network.evaluate(Tensor::new(vec![2], vec![0.0, 1.0]), Tensor::new(vec![2], vec![0.0, 1.0]), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Validation split must be between 0 and 1")]
+    fn test_fit_invalid_validation_split() {
+        let mut network = linear_network();
+        network.fit(
+            Tensor::new(vec![2], vec![0.0, 1.0]),
+            Tensor::new(vec![2], vec![0.0, 1.0]),
+            1,
+            1,
+            Validation::Split {
+                fraction: 1.0,
+                shuffle: false,
+            },
+            &mut [],
+        );
+    }
 }
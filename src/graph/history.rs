@@ -0,0 +1,104 @@
+use std::{collections::BTreeMap, fs};
+
+use serde_json::{json, Map, Value};
+
+// Values recorded once per epoch during training, keyed by name: "loss", "lr", the name of every
+// compiled metric, and their "val_" counterparts when validating
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct History {
+    values: BTreeMap<String, Vec<f64>>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    pub fn record(&mut self, name: &str, value: f64) {
+        self.values.entry(name.to_string()).or_default().push(value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&[f64]> {
+        self.values.get(name).map(|values| values.as_slice())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.values.keys().map(|name| name.as_str()).collect()
+    }
+
+    // Number of epochs recorded, taken from the longest series
+    pub fn epochs(&self) -> usize {
+        self.values.values().map(|values| values.len()).max().unwrap_or(0)
+    }
+
+    // An object mapping every name to its array of per-epoch values
+    pub fn to_json(&self) -> Value {
+        let values = self
+            .values
+            .iter()
+            .map(|(name, values)| (name.clone(), json!(values)))
+            .collect::<Map<String, Value>>();
+        Value::Object(values)
+    }
+
+    pub fn from_json(value: &Value) -> Option<History> {
+        let mut history = History::new();
+        for (name, values) in value.as_object()? {
+            let values = values
+                .as_array()?
+                .iter()
+                .map(|value| value.as_f64())
+                .collect::<Option<Vec<f64>>>()?;
+            history.values.insert(name.clone(), values);
+        }
+        Some(history)
+    }
+
+    pub fn save(&self, path: &str) -> std::io::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(&self.to_json())?)
+    }
+
+    pub fn load(path: &str) -> Result<History, &'static str> {
+        let contents = fs::read_to_string(path).map_err(|_| "Error reading file")?;
+        let value: Value = serde_json::from_str(&contents).map_err(|_| "Error parsing history")?;
+        History::from_json(&value).ok_or("History is not an object of number arrays")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let mut history = History::new();
+        history.record("loss", 0.5);
+        history.record("loss", 0.25);
+        history.record("accuracy", 0.9);
+        assert_eq!(history.get("loss"), Some(&[0.5, 0.25][..]));
+        assert_eq!(history.get("val_loss"), None);
+        assert_eq!(history.names(), vec!["accuracy", "loss"]);
+        assert_eq!(history.epochs(), 2);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let mut history = History::new();
+        history.record("loss", 0.5);
+        history.record("lr", 0.01);
+        assert_eq!(history.to_json(), json!({"loss": [0.5], "lr": [0.01]}));
+        assert_eq!(History::from_json(&history.to_json()), Some(history));
+        assert_eq!(History::from_json(&json!({"loss": ["high"]})), None);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let mut history = History::new();
+        history.record("loss", 0.125);
+        let path = std::env::temp_dir().join("history_test_save_and_load.json");
+        let path = path.to_str().unwrap();
+        history.save(path).unwrap();
+        assert_eq!(History::load(path), Ok(history));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use std::f64::consts::PI;
+
+// Whether a schedule advances after every optimizer step or after every epoch
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ScheduleInterval {
+    Step,
+    Epoch,
+}
+
+pub trait LearningRateSchedule {
+    // Learning rate to use for the next optimizer step
+    fn learning_rate(&self) -> f64;
+    // Called after every optimizer step
+    fn on_step_end(&mut self) {}
+    // Called at the end of every epoch with the loss the schedule should watch
+    fn on_epoch_end(&mut self, _loss: f64) {}
+}
+
+// Counts steps or epochs depending on the interval
+#[derive(Debug, Clone)]
+struct Counter {
+    interval: ScheduleInterval,
+    count: usize,
+}
+
+impl Counter {
+    fn new(interval: ScheduleInterval) -> Counter {
+        Counter { interval, count: 0 }
+    }
+
+    fn on_step_end(&mut self) {
+        if self.interval == ScheduleInterval::Step {
+            self.count += 1;
+        }
+    }
+
+    fn on_epoch_end(&mut self) {
+        if self.interval == ScheduleInterval::Epoch {
+            self.count += 1;
+        }
+    }
+}
+
+// Multiplies the learning rate by drop_factor every period steps or epochs
+pub struct StepDecay {
+    pub initial_learning_rate: f64,
+    pub drop_factor: f64,
+    pub period: usize,
+    counter: Counter,
+}
+
+impl StepDecay {
+    pub fn new(
+        initial_learning_rate: f64,
+        drop_factor: f64,
+        period: usize,
+        interval: ScheduleInterval,
+    ) -> StepDecay {
+        if period == 0 {
+            panic!("StepDecay period must be at least 1");
+        }
+        StepDecay {
+            initial_learning_rate,
+            drop_factor,
+            period,
+            counter: Counter::new(interval),
+        }
+    }
+}
+
+impl LearningRateSchedule for StepDecay {
+    fn learning_rate(&self) -> f64 {
+        let drops = (self.counter.count / self.period) as i32;
+        self.initial_learning_rate * self.drop_factor.powi(drops)
+    }
+
+    fn on_step_end(&mut self) {
+        self.counter.on_step_end();
+    }
+
+    fn on_epoch_end(&mut self, _loss: f64) {
+        self.counter.on_epoch_end();
+    }
+}
+
+// Smoothly multiplies the learning rate by decay_rate every decay_length steps or epochs
+pub struct ExponentialDecay {
+    pub initial_learning_rate: f64,
+    pub decay_rate: f64,
+    pub decay_length: usize,
+    counter: Counter,
+}
+
+impl ExponentialDecay {
+    pub fn new(
+        initial_learning_rate: f64,
+        decay_rate: f64,
+        decay_length: usize,
+        interval: ScheduleInterval,
+    ) -> ExponentialDecay {
+        if decay_length == 0 {
+            panic!("ExponentialDecay decay_length must be at least 1");
+        }
+        ExponentialDecay {
+            initial_learning_rate,
+            decay_rate,
+            decay_length,
+            counter: Counter::new(interval),
+        }
+    }
+}
+
+impl LearningRateSchedule for ExponentialDecay {
+    fn learning_rate(&self) -> f64 {
+        let exponent = self.counter.count as f64 / self.decay_length as f64;
+        self.initial_learning_rate * self.decay_rate.powf(exponent)
+    }
+
+    fn on_step_end(&mut self) {
+        self.counter.on_step_end();
+    }
+
+    fn on_epoch_end(&mut self, _loss: f64) {
+        self.counter.on_epoch_end();
+    }
+}
+
+// Anneals from max to min learning rate along a half cosine, then restarts at the max. Every
+// cycle is period_multiplier times as long as the one before it.
+pub struct CosineAnnealingWarmRestarts {
+    pub max_learning_rate: f64,
+    pub min_learning_rate: f64,
+    pub first_period: usize,
+    pub period_multiplier: usize,
+    counter: Counter,
+}
+
+impl CosineAnnealingWarmRestarts {
+    pub fn new(
+        max_learning_rate: f64,
+        min_learning_rate: f64,
+        first_period: usize,
+        period_multiplier: usize,
+        interval: ScheduleInterval,
+    ) -> CosineAnnealingWarmRestarts {
+        if first_period == 0 {
+            panic!("CosineAnnealingWarmRestarts first_period must be at least 1");
+        }
+        if period_multiplier == 0 {
+            panic!("CosineAnnealingWarmRestarts period_multiplier must be at least 1");
+        }
+        CosineAnnealingWarmRestarts {
+            max_learning_rate,
+            min_learning_rate,
+            first_period,
+            period_multiplier,
+            counter: Counter::new(interval),
+        }
+    }
+
+    // Position within the current cycle and the length of that cycle
+    fn cycle_position(&self) -> (usize, usize) {
+        let mut position = self.counter.count;
+        let mut period = self.first_period;
+        while position >= period {
+            position -= period;
+            period *= self.period_multiplier;
+        }
+        (position, period)
+    }
+}
+
+impl LearningRateSchedule for CosineAnnealingWarmRestarts {
+    fn learning_rate(&self) -> f64 {
+        let (position, period) = self.cycle_position();
+        let progress = position as f64 / period as f64;
+        self.min_learning_rate
+            + 0.5 * (self.max_learning_rate - self.min_learning_rate) * (1.0 + (PI * progress).cos())
+    }
+
+    fn on_step_end(&mut self) {
+        self.counter.on_step_end();
+    }
+
+    fn on_epoch_end(&mut self, _loss: f64) {
+        self.counter.on_epoch_end();
+    }
+}
+
+// Ramps the learning rate up linearly to that of the wrapped schedule over warmup_length steps or
+// epochs, then hands over to the wrapped schedule, which only starts advancing after the warmup
+pub struct LinearWarmup {
+    pub warmup_length: usize,
+    schedule: Box<dyn LearningRateSchedule>,
+    counter: Counter,
+}
+
+impl LinearWarmup {
+    pub fn new(
+        warmup_length: usize,
+        schedule: Box<dyn LearningRateSchedule>,
+        interval: ScheduleInterval,
+    ) -> LinearWarmup {
+        LinearWarmup {
+            warmup_length,
+            schedule,
+            counter: Counter::new(interval),
+        }
+    }
+
+    fn warming_up(&self) -> bool {
+        self.counter.count < self.warmup_length
+    }
+}
+
+impl LearningRateSchedule for LinearWarmup {
+    fn learning_rate(&self) -> f64 {
+        if self.warming_up() {
+            let progress = (self.counter.count + 1) as f64 / self.warmup_length as f64;
+            self.schedule.learning_rate() * progress
+        } else {
+            self.schedule.learning_rate()
+        }
+    }
+
+    fn on_step_end(&mut self) {
+        if self.warming_up() {
+            self.counter.on_step_end();
+        } else {
+            self.schedule.on_step_end();
+        }
+    }
+
+    fn on_epoch_end(&mut self, loss: f64) {
+        if self.warming_up() {
+            self.counter.on_epoch_end();
+        } else {
+            self.schedule.on_epoch_end(loss);
+        }
+    }
+}
+
+// Multiplies the learning rate by factor once the loss has gone patience epochs without
+// improving by at least min_delta, never going below min_learning_rate
+pub struct ReduceOnPlateau {
+    pub factor: f64,
+    pub patience: usize,
+    pub min_delta: f64,
+    pub min_learning_rate: f64,
+    learning_rate: f64,
+    best_loss: f64,
+    epochs_without_improvement: usize,
+}
+
+impl ReduceOnPlateau {
+    pub fn new(
+        initial_learning_rate: f64,
+        factor: f64,
+        patience: usize,
+        min_delta: f64,
+        min_learning_rate: f64,
+    ) -> ReduceOnPlateau {
+        ReduceOnPlateau {
+            factor,
+            patience,
+            min_delta,
+            min_learning_rate,
+            learning_rate: initial_learning_rate,
+            best_loss: f64::INFINITY,
+            epochs_without_improvement: 0,
+        }
+    }
+}
+
+impl LearningRateSchedule for ReduceOnPlateau {
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn on_epoch_end(&mut self, loss: f64) {
+        if loss < self.best_loss - self.min_delta {
+            self.best_loss = loss;
+            self.epochs_without_improvement = 0;
+            return;
+        }
+
+        self.epochs_without_improvement += 1;
+        if self.epochs_without_improvement > self.patience {
+            self.learning_rate = (self.learning_rate * self.factor).max(self.min_learning_rate);
+            self.epochs_without_improvement = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_step_decay() {
+        let mut schedule = StepDecay::new(1.0, 0.5, 2, ScheduleInterval::Epoch);
+        let mut rates = vec![];
+        for _ in 0..5 {
+            rates.push(schedule.learning_rate());
+            // Steps do not advance an epoch based schedule
+            schedule.on_step_end();
+            schedule.on_epoch_end(0.0);
+        }
+        assert_eq!(rates, vec![1.0, 1.0, 0.5, 0.5, 0.25]);
+    }
+
+    #[test]
+    fn test_exponential_decay() {
+        let mut schedule = ExponentialDecay::new(1.0, 0.5, 2, ScheduleInterval::Step);
+        schedule.on_step_end();
+        assert_close(schedule.learning_rate(), 0.5_f64.sqrt());
+        schedule.on_step_end();
+        assert_close(schedule.learning_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_cosine_annealing_warm_restarts() {
+        let mut schedule = CosineAnnealingWarmRestarts::new(1.0, 0.0, 2, 2, ScheduleInterval::Step);
+        let mut rates = vec![];
+        for _ in 0..7 {
+            rates.push(schedule.learning_rate());
+            schedule.on_step_end();
+        }
+        // Cycles of length 2 then 4
+        let expected = [1.0, 0.5, 1.0, 0.5 + 0.5 * (PI / 4.0).cos(), 0.5, 0.5 - 0.5 * (PI / 4.0).cos(), 1.0];
+        rates.iter().zip(expected.iter()).for_each(|(a, b)| assert_close(*a, *b));
+    }
+
+    #[test]
+    #[should_panic(expected = "StepDecay period must be at least 1")]
+    fn test_step_decay_zero_period() {
+        StepDecay::new(1.0, 0.5, 0, ScheduleInterval::Epoch);
+    }
+
+    #[test]
+    #[should_panic(expected = "ExponentialDecay decay_length must be at least 1")]
+    fn test_exponential_decay_zero_length() {
+        ExponentialDecay::new(1.0, 0.5, 0, ScheduleInterval::Step);
+    }
+
+    #[test]
+    #[should_panic(expected = "CosineAnnealingWarmRestarts first_period must be at least 1")]
+    fn test_cosine_annealing_zero_period() {
+        CosineAnnealingWarmRestarts::new(1.0, 0.0, 0, 2, ScheduleInterval::Step);
+    }
+
+    #[test]
+    #[should_panic(expected = "CosineAnnealingWarmRestarts period_multiplier must be at least 1")]
+    fn test_cosine_annealing_zero_multiplier() {
+        CosineAnnealingWarmRestarts::new(1.0, 0.0, 2, 0, ScheduleInterval::Step);
+    }
+
+    #[test]
+    fn test_linear_warmup() {
+        let decay = StepDecay::new(1.0, 0.5, 1, ScheduleInterval::Step);
+        let mut schedule = LinearWarmup::new(4, Box::new(decay), ScheduleInterval::Step);
+        let mut rates = vec![];
+        for _ in 0..6 {
+            rates.push(schedule.learning_rate());
+            schedule.on_step_end();
+        }
+        assert_eq!(rates, vec![0.25, 0.5, 0.75, 1.0, 1.0, 0.5]);
+    }
+
+    #[test]
+    fn test_reduce_on_plateau() {
+        let mut schedule = ReduceOnPlateau::new(1.0, 0.1, 1, 0.0, 0.005);
+        schedule.on_epoch_end(1.0);
+        schedule.on_epoch_end(0.9);
+        schedule.on_epoch_end(0.95);
+        assert_eq!(schedule.learning_rate(), 1.0);
+        schedule.on_epoch_end(0.95);
+        assert_close(schedule.learning_rate(), 0.1);
+        schedule.on_epoch_end(0.8);
+        schedule.on_epoch_end(0.8);
+        schedule.on_epoch_end(0.8);
+        assert_close(schedule.learning_rate(), 0.01);
+        schedule.on_epoch_end(0.8);
+        schedule.on_epoch_end(0.8);
+        assert_eq!(schedule.learning_rate(), 0.005);
+    }
+}
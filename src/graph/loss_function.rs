@@ -4,32 +4,43 @@ use crate::math::{tensor::Tensor, tensor_context::{self, TensorContext, TensorRe
 
 pub enum LossFunction {
     MeanSquaredError,
-    CrossEntropy,
-    SparseCrossEntropy,
+    // Labels are one distribution over the classes per sample. from_logits says whether the
+    // network outputs raw scores, which are normalised with a fused softmax, or probabilities.
+    CrossEntropy { from_logits: bool },
+    // Labels are one class index per sample
+    SparseCrossEntropy { from_logits: bool },
 }
 
 impl LossFunction {
     pub fn loss(&self,tensor_context: Rc<RefCell<TensorContext>>,  input : TensorRef, desired: TensorRef) -> TensorRef {
         match self {
             LossFunction::MeanSquaredError => LossFunction::mean_squared_error(tensor_context, input, desired),
-            LossFunction::CrossEntropy => LossFunction::cross_entropy(tensor_context, input, desired),
-            LossFunction::SparseCrossEntropy => LossFunction::sparse_cross_entropy(tensor_context, input, desired),
+            LossFunction::CrossEntropy { from_logits } => LossFunction::cross_entropy(tensor_context, input, desired, *from_logits),
+            LossFunction::SparseCrossEntropy { from_logits } => LossFunction::sparse_cross_entropy(tensor_context, input, desired, *from_logits),
+        }
+    }
+
+    // Shape of the labels for one sample, given the shape of the network output for one sample
+    pub fn label_shape(&self, output_shape: &[usize]) -> Vec<usize> {
+        match self {
+            LossFunction::SparseCrossEntropy { .. } => vec![],
+            _ => output_shape.to_vec(),
         }
     }
 
     fn mean_squared_error(tensor_context: Rc<RefCell<TensorContext>>, input : TensorRef, desired: TensorRef) -> TensorRef {
         let loss_tensor = tensor_context.borrow_mut().sub(input, desired);
         let loss_tensor = tensor_context.borrow_mut().pow(loss_tensor, 2.0);
-        let loss = tensor_context.borrow_mut().sum(loss_tensor);
+        let loss = tensor_context.borrow_mut().mean(loss_tensor);
 
         loss
     }
 
-    fn cross_entropy(tensor_context: Rc<RefCell<TensorContext>>,_input : TensorRef, _desired: TensorRef) -> TensorRef {
-        todo!("Implement Cross Entropy Loss Function")
+    fn cross_entropy(tensor_context: Rc<RefCell<TensorContext>>, input : TensorRef, desired: TensorRef, from_logits: bool) -> TensorRef {
+        tensor_context.borrow_mut().cross_entropy(input, desired, from_logits)
     }
 
-    fn sparse_cross_entropy(tensor_context: Rc<RefCell<TensorContext>>,_input : TensorRef, _desired: TensorRef) -> TensorRef {
-        todo!("Implement Sparse Cross Entropy Loss Function")
+    fn sparse_cross_entropy(tensor_context: Rc<RefCell<TensorContext>>, input : TensorRef, desired: TensorRef, from_logits: bool) -> TensorRef {
+        tensor_context.borrow_mut().sparse_cross_entropy(input, desired, from_logits)
     }
 }
\ No newline at end of file
@@ -1,5 +1,579 @@
+// How per-class scores are combined for multiclass outputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Average {
+    // Pools the counts of every class before dividing
+    Micro,
+    // Takes the unweighted mean of the per-class scores
+    Macro,
+}
+
+// Classification metrics are computed over class predictions. A network with a single output is a
+// binary classifier whose output is thresholded at 0.5, and precision, recall and F1 are then
+// those of the positive class whatever the averaging. Otherwise the predicted class is the
+// largest output. Regression metrics compare every output with its label.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Metric {
     Accuracy,
-    Precision,
-    Recall
-}
\ No newline at end of file
+    Precision(Average),
+    Recall(Average),
+    F1(Average),
+    // Whether the label is among the k largest outputs
+    TopKAccuracy(usize),
+    // Area under the ROC curve of a binary classifier
+    RocAuc,
+    MeanAbsoluteError,
+    RootMeanSquaredError,
+    // 1 - SSE / SST. Constant labels have no variance to explain, so any error scores 0 there and
+    // only a perfect fit scores 1.
+    RSquared,
+}
+
+// Running state of a metric, updated one batch at a time. Predictions hold one row of outputs per
+// sample. Labels hold either one class index per sample or a row of the same width as the
+// predictions, such as a one-hot encoding or regression targets.
+pub trait MetricAccumulator {
+    fn update(&mut self, predictions: &[f64], labels: &[f64], samples: usize);
+    fn result(&self) -> f64;
+    fn reset(&mut self);
+}
+
+impl Metric {
+    pub fn name(&self) -> String {
+        match self {
+            Metric::Accuracy => "accuracy".to_string(),
+            Metric::Precision(average) => format!("precision_{}", average_name(*average)),
+            Metric::Recall(average) => format!("recall_{}", average_name(*average)),
+            Metric::F1(average) => format!("f1_{}", average_name(*average)),
+            Metric::TopKAccuracy(k) => format!("top_{}_accuracy", k),
+            Metric::RocAuc => "roc_auc".to_string(),
+            Metric::MeanAbsoluteError => "mae".to_string(),
+            Metric::RootMeanSquaredError => "rmse".to_string(),
+            Metric::RSquared => "r2".to_string(),
+        }
+    }
+
+    pub fn accumulator(&self) -> Box<dyn MetricAccumulator> {
+        match self {
+            Metric::Accuracy | Metric::Precision(_) | Metric::Recall(_) | Metric::F1(_) => {
+                Box::new(ClassificationAccumulator {
+                    metric: *self,
+                    confusion_matrix: ConfusionMatrix::default(),
+                })
+            }
+            Metric::TopKAccuracy(k) => Box::new(TopKAccumulator {
+                k: *k,
+                correct: 0,
+                samples: 0,
+            }),
+            Metric::RocAuc => Box::new(RocAucAccumulator { scores: vec![] }),
+            Metric::MeanAbsoluteError | Metric::RootMeanSquaredError | Metric::RSquared => {
+                Box::new(RegressionAccumulator {
+                    metric: *self,
+                    count: 0,
+                    absolute_error_sum: 0.0,
+                    squared_error_sum: 0.0,
+                    label_mean: 0.0,
+                    label_m2: 0.0,
+                })
+            }
+        }
+    }
+
+    // Computes the metric over a whole dataset at once
+    pub fn compute(&self, predictions: &[f64], labels: &[f64], samples: usize) -> f64 {
+        let mut accumulator = self.accumulator();
+        accumulator.update(predictions, labels, samples);
+        accumulator.result()
+    }
+}
+
+fn average_name(average: Average) -> &'static str {
+    match average {
+        Average::Micro => "micro",
+        Average::Macro => "macro",
+    }
+}
+
+// Converts a sparse label into a class index below classes
+fn class_index(label: f64, classes: usize) -> usize {
+    if label.fract() != 0.0 || label < 0.0 || label >= classes as f64 {
+        panic!("Label {} is not a class index below {}", label, classes);
+    }
+    label as usize
+}
+
+fn ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator == 0.0 {
+        0.0
+    } else {
+        numerator / denominator
+    }
+}
+
+// Counts of every (actual, predicted) class pair. A single output per sample is treated as two
+// classes, negative and positive. The number of classes is taken from the first batch unless
+// given up front.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfusionMatrix {
+    classes: usize,
+    binary: bool,
+    counts: Vec<usize>,
+}
+
+impl ConfusionMatrix {
+    pub fn new(classes: usize) -> ConfusionMatrix {
+        ConfusionMatrix {
+            classes,
+            binary: false,
+            counts: vec![0; classes * classes],
+        }
+    }
+
+    pub fn update(&mut self, predictions: &[f64], labels: &[f64], samples: usize) {
+        if samples == 0 {
+            return;
+        }
+        let width = predictions.len() / samples;
+        if self.classes == 0 {
+            *self = ConfusionMatrix::new(width.max(2));
+            self.binary = width == 1;
+        }
+
+        for sample in 0..samples {
+            let predicted = predicted_class(&predictions[sample * width..(sample + 1) * width]);
+            let actual = if labels.len() == samples {
+                if self.binary {
+                    (labels[sample] >= 0.5) as usize
+                } else {
+                    class_index(labels[sample], self.classes)
+                }
+            } else {
+                predicted_class(&labels[sample * width..(sample + 1) * width])
+            };
+            if actual >= self.classes || predicted >= self.classes {
+                panic!(
+                    "Class {} is outside a confusion matrix of {} classes",
+                    actual.max(predicted),
+                    self.classes
+                );
+            }
+            self.counts[actual * self.classes + predicted] += 1;
+        }
+    }
+
+    pub fn classes(&self) -> usize {
+        self.classes
+    }
+
+    // Number of samples of the actual class that were predicted as the predicted class
+    pub fn get(&self, actual: usize, predicted: usize) -> usize {
+        self.counts[actual * self.classes + predicted]
+    }
+
+    pub fn true_positives(&self, class: usize) -> usize {
+        self.get(class, class)
+    }
+
+    // Number of samples predicted as the class
+    pub fn predicted(&self, class: usize) -> usize {
+        (0..self.classes).map(|actual| self.get(actual, class)).sum()
+    }
+
+    // Number of samples that belong to the class
+    pub fn actual(&self, class: usize) -> usize {
+        (0..self.classes).map(|predicted| self.get(class, predicted)).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let correct = (0..self.classes).map(|class| self.true_positives(class)).sum::<usize>();
+        ratio(correct as f64, self.total() as f64)
+    }
+
+    pub fn precision(&self, average: Average) -> f64 {
+        self.score(average, |class| {
+            (self.true_positives(class) as f64, self.predicted(class) as f64)
+        })
+    }
+
+    pub fn recall(&self, average: Average) -> f64 {
+        self.score(average, |class| {
+            (self.true_positives(class) as f64, self.actual(class) as f64)
+        })
+    }
+
+    pub fn f1(&self, average: Average) -> f64 {
+        // F1 is 2·TP / (2·TP + FP + FN), which is the same as dividing by predicted plus actual
+        self.score(average, |class| {
+            (
+                2.0 * self.true_positives(class) as f64,
+                (self.predicted(class) + self.actual(class)) as f64,
+            )
+        })
+    }
+
+    // Combines the per-class numerators and denominators of a score
+    fn score(&self, average: Average, terms: impl Fn(usize) -> (f64, f64)) -> f64 {
+        if self.classes == 0 {
+            return 0.0;
+        }
+        if self.binary {
+            let (numerator, denominator) = terms(1);
+            return ratio(numerator, denominator);
+        }
+
+        match average {
+            Average::Micro => {
+                let (numerator, denominator) = (0..self.classes)
+                    .map(&terms)
+                    .fold((0.0, 0.0), |(a, b), (c, d)| (a + c, b + d));
+                ratio(numerator, denominator)
+            }
+            Average::Macro => {
+                (0..self.classes)
+                    .map(|class| {
+                        let (numerator, denominator) = terms(class);
+                        ratio(numerator, denominator)
+                    })
+                    .sum::<f64>()
+                    / self.classes as f64
+            }
+        }
+    }
+}
+
+// Index of the largest value in a row, or whether a single probability is at least 0.5
+fn predicted_class(row: &[f64]) -> usize {
+    if row.len() == 1 {
+        return (row[0] >= 0.5) as usize;
+    }
+    row.iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(index, _)| index)
+        .unwrap()
+}
+
+struct ClassificationAccumulator {
+    metric: Metric,
+    confusion_matrix: ConfusionMatrix,
+}
+
+impl MetricAccumulator for ClassificationAccumulator {
+    fn update(&mut self, predictions: &[f64], labels: &[f64], samples: usize) {
+        self.confusion_matrix.update(predictions, labels, samples);
+    }
+
+    fn result(&self) -> f64 {
+        match self.metric {
+            Metric::Precision(average) => self.confusion_matrix.precision(average),
+            Metric::Recall(average) => self.confusion_matrix.recall(average),
+            Metric::F1(average) => self.confusion_matrix.f1(average),
+            _ => self.confusion_matrix.accuracy(),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.confusion_matrix = ConfusionMatrix::default();
+    }
+}
+
+struct TopKAccumulator {
+    k: usize,
+    correct: usize,
+    samples: usize,
+}
+
+impl MetricAccumulator for TopKAccumulator {
+    fn update(&mut self, predictions: &[f64], labels: &[f64], samples: usize) {
+        if samples == 0 {
+            return;
+        }
+        let width = predictions.len() / samples;
+        if width < 2 {
+            panic!("Top-k accuracy needs one output per class");
+        }
+
+        for sample in 0..samples {
+            let row = &predictions[sample * width..(sample + 1) * width];
+            let label = if labels.len() == samples {
+                class_index(labels[sample], width)
+            } else {
+                predicted_class(&labels[sample * width..(sample + 1) * width])
+            };
+            // The label is in the top k when fewer than k outputs beat it
+            let rank = row.iter().filter(|a| **a > row[label]).count();
+            if rank < self.k {
+                self.correct += 1;
+            }
+        }
+        self.samples += samples;
+    }
+
+    fn result(&self) -> f64 {
+        ratio(self.correct as f64, self.samples as f64)
+    }
+
+    fn reset(&mut self) {
+        self.correct = 0;
+        self.samples = 0;
+    }
+}
+
+// Keeps every score so the area can be computed exactly
+struct RocAucAccumulator {
+    scores: Vec<(f64, bool)>,
+}
+
+impl MetricAccumulator for RocAucAccumulator {
+    fn update(&mut self, predictions: &[f64], labels: &[f64], samples: usize) {
+        if predictions.len() != samples || labels.len() != samples {
+            panic!("ROC-AUC needs a single output and label per sample");
+        }
+        self.scores.extend(
+            predictions
+                .iter()
+                .zip(labels.iter())
+                .map(|(prediction, label)| (*prediction, *label >= 0.5)),
+        );
+    }
+
+    // The probability that a random positive scores higher than a random negative, counting ties
+    // as half
+    fn result(&self) -> f64 {
+        let mut scores = self.scores.clone();
+        scores.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let mut negatives_below = 0.0;
+        let mut area = 0.0;
+        let mut start = 0;
+        while start < scores.len() {
+            let end = start
+                + scores[start..]
+                    .iter()
+                    .take_while(|(score, _)| *score == scores[start].0)
+                    .count();
+            let positives = scores[start..end].iter().filter(|(_, positive)| *positive).count() as f64;
+            let negatives = (end - start) as f64 - positives;
+            area += positives * (negatives_below + 0.5 * negatives);
+            negatives_below += negatives;
+            start = end;
+        }
+
+        let positives = scores.iter().filter(|(_, positive)| *positive).count() as f64;
+        ratio(area, positives * negatives_below)
+    }
+
+    fn reset(&mut self) {
+        self.scores.clear();
+    }
+}
+
+struct RegressionAccumulator {
+    metric: Metric,
+    count: usize,
+    absolute_error_sum: f64,
+    squared_error_sum: f64,
+    // Running mean of the labels and sum of squared deviations from it, updated with Welford's
+    // method so labels far from zero do not cancel
+    label_mean: f64,
+    label_m2: f64,
+}
+
+impl MetricAccumulator for RegressionAccumulator {
+    fn update(&mut self, predictions: &[f64], labels: &[f64], _samples: usize) {
+        if predictions.len() != labels.len() {
+            panic!("Got {} predictions but {} labels", predictions.len(), labels.len());
+        }
+        for (prediction, label) in predictions.iter().zip(labels.iter()) {
+            self.absolute_error_sum += (prediction - label).abs();
+            self.squared_error_sum += (prediction - label).powi(2);
+            self.count += 1;
+            let deviation = label - self.label_mean;
+            self.label_mean += deviation / self.count as f64;
+            self.label_m2 += deviation * (label - self.label_mean);
+        }
+    }
+
+    fn result(&self) -> f64 {
+        let count = self.count as f64;
+        match self.metric {
+            Metric::MeanAbsoluteError => ratio(self.absolute_error_sum, count),
+            Metric::RootMeanSquaredError => ratio(self.squared_error_sum, count).sqrt(),
+            _ => {
+                // Constant labels leave every deviation, and so the total sum of squares, exactly 0
+                if self.label_m2 > 0.0 {
+                    1.0 - self.squared_error_sum / self.label_m2
+                } else if self.squared_error_sum == 0.0 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.absolute_error_sum = 0.0;
+        self.squared_error_sum = 0.0;
+        self.label_mean = 0.0;
+        self.label_m2 = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-12, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_binary_metrics() {
+        let predictions = [0.9, 0.2, 0.7, 0.4];
+        let labels = [1.0, 0.0, 0.0, 1.0];
+        assert_eq!(Metric::Accuracy.compute(&predictions, &labels, 4), 0.5);
+        assert_eq!(Metric::Precision(Average::Macro).compute(&predictions, &labels, 4), 0.5);
+        assert_eq!(Metric::Recall(Average::Micro).compute(&predictions, &labels, 4), 0.5);
+        assert_eq!(Metric::F1(Average::Micro).compute(&predictions, &labels, 4), 0.5);
+    }
+
+    #[test]
+    fn test_multiclass_metrics() {
+        // Predicted classes 0, 1, 1, 2 against labels 0, 1, 2, 2
+        let predictions = [
+            0.8, 0.1, 0.1, //
+            0.1, 0.8, 0.1, //
+            0.2, 0.5, 0.3, //
+            0.0, 0.1, 0.9,
+        ];
+        let labels = [0.0, 1.0, 2.0, 2.0];
+        assert_eq!(Metric::Accuracy.compute(&predictions, &labels, 4), 0.75);
+        assert_eq!(Metric::Precision(Average::Micro).compute(&predictions, &labels, 4), 0.75);
+        // Per class precision 1, 1/2, 1 and recall 1, 1, 1/2
+        let macro_precision = Metric::Precision(Average::Macro).compute(&predictions, &labels, 4);
+        let macro_recall = Metric::Recall(Average::Macro).compute(&predictions, &labels, 4);
+        assert_close(macro_precision, 2.5 / 3.0);
+        assert_close(macro_recall, 2.5 / 3.0);
+        // Per class F1 1, 2/3, 2/3
+        let macro_f1 = Metric::F1(Average::Macro).compute(&predictions, &labels, 4);
+        assert_close(macro_f1, (1.0 + 4.0 / 3.0) / 3.0);
+    }
+
+    #[test]
+    fn test_one_hot_labels() {
+        let predictions = [0.8, 0.2, 0.3, 0.7];
+        let labels = [1.0, 0.0, 1.0, 0.0];
+        assert_eq!(Metric::Accuracy.compute(&predictions, &labels, 2), 0.5);
+        // Class 1 never occurs, so its recall counts as zero
+        assert_eq!(Metric::Recall(Average::Macro).compute(&predictions, &labels, 2), 0.25);
+    }
+
+    #[test]
+    fn test_streaming_matches_whole_dataset() {
+        let predictions = [0.8, 0.1, 0.1, 0.1, 0.8, 0.1, 0.2, 0.5, 0.3, 0.0, 0.1, 0.9];
+        let labels = [0.0, 1.0, 2.0, 2.0];
+        let metric = Metric::F1(Average::Macro);
+        let mut accumulator = metric.accumulator();
+        accumulator.update(&predictions[..3], &labels[..1], 1);
+        accumulator.update(&predictions[3..], &labels[1..], 3);
+        assert_eq!(accumulator.result(), metric.compute(&predictions, &labels, 4));
+
+        // Only class 0 is left after a reset, so the other two classes score zero
+        accumulator.reset();
+        accumulator.update(&predictions[..3], &labels[..1], 1);
+        assert_eq!(accumulator.result(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn test_confusion_matrix() {
+        let mut confusion_matrix = ConfusionMatrix::new(3);
+        confusion_matrix.update(&[0.1, 0.8, 0.1, 0.2, 0.5, 0.3], &[1.0, 2.0], 2);
+        assert_eq!(confusion_matrix.get(1, 1), 1);
+        assert_eq!(confusion_matrix.get(2, 1), 1);
+        assert_eq!(confusion_matrix.predicted(1), 2);
+        assert_eq!(confusion_matrix.actual(2), 1);
+        assert_eq!(confusion_matrix.total(), 2);
+    }
+
+    #[test]
+    fn test_top_k_accuracy() {
+        let predictions = [0.5, 0.3, 0.2, 0.1, 0.2, 0.7];
+        let labels = [1.0, 0.0];
+        assert_eq!(Metric::TopKAccuracy(1).compute(&predictions, &labels, 2), 0.0);
+        assert_eq!(Metric::TopKAccuracy(2).compute(&predictions, &labels, 2), 0.5);
+        assert_eq!(Metric::TopKAccuracy(3).compute(&predictions, &labels, 2), 1.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Label 3 is not a class index below 3")]
+    fn test_top_k_accuracy_label_out_of_range() {
+        Metric::TopKAccuracy(1).compute(&[0.5, 0.3, 0.2], &[3.0], 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Label -1 is not a class index below 3")]
+    fn test_confusion_matrix_negative_label() {
+        Metric::Accuracy.compute(&[0.5, 0.3, 0.2], &[-1.0], 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Label 1.7 is not a class index below 3")]
+    fn test_confusion_matrix_fractional_label() {
+        ConfusionMatrix::default().update(&[0.5, 0.3, 0.2], &[1.7], 1);
+    }
+
+    #[test]
+    fn test_roc_auc() {
+        // Of the four positive/negative pairs three are ordered correctly
+        let predictions = [0.1, 0.4, 0.35, 0.8];
+        let labels = [0.0, 0.0, 1.0, 1.0];
+        assert_close(Metric::RocAuc.compute(&predictions, &labels, 4), 0.75);
+
+        // Tied scores count as half
+        assert_close(Metric::RocAuc.compute(&[0.5, 0.5], &[0.0, 1.0], 2), 0.5);
+    }
+
+    #[test]
+    fn test_regression_metrics() {
+        let predictions = [1.0, 2.0, 4.0];
+        let labels = [1.0, 3.0, 2.0];
+        assert_close(Metric::MeanAbsoluteError.compute(&predictions, &labels, 3), 1.0);
+        assert_close(Metric::RootMeanSquaredError.compute(&predictions, &labels, 3), (5.0_f64 / 3.0).sqrt());
+        // Labels have mean 2 and a total sum of squares of 2
+        assert_close(Metric::RSquared.compute(&predictions, &labels, 3), 1.0 - 5.0 / 2.0);
+    }
+
+    #[test]
+    fn test_r_squared_constant_labels() {
+        let labels = [0.1, 0.1, 0.1];
+        assert_eq!(Metric::RSquared.compute(&[0.1, 0.2, 0.1], &labels, 3), 0.0);
+        assert_eq!(Metric::RSquared.compute(&labels, &labels, 3), 1.0);
+    }
+
+    #[test]
+    fn test_r_squared_offset_labels() {
+        // A total sum of squares of 0.5 and squared errors of 0.02, far from zero
+        let labels = [1e8, 1e8 + 1.0];
+        let r_squared = Metric::RSquared.compute(&[1e8 + 0.1, 1e8 + 0.9], &labels, 2);
+        assert!((r_squared - 0.96).abs() < 1e-6, "{}", r_squared);
+
+        // Split over batches the running mean and deviations are merged the same way
+        let mut accumulator = Metric::RSquared.accumulator();
+        accumulator.update(&[1e8 + 0.1], &labels[..1], 1);
+        accumulator.update(&[1e8 + 0.9], &labels[1..], 1);
+        assert!((accumulator.result() - r_squared).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!(Metric::Precision(Average::Macro).name(), "precision_macro");
+        assert_eq!(Metric::Recall(Average::Micro).name(), "recall_micro");
+        assert_eq!(Metric::TopKAccuracy(5).name(), "top_5_accuracy");
+    }
+}
@@ -1,7 +1,398 @@
-pub enum Optimizer {
-    SGD,
-    Momentum,
-    AdaGrad,
-    RMSprop,
-    Adam,
-}
\ No newline at end of file
+use std::collections::HashMap;
+
+use crate::math::tensor_context::{TensorContext, TensorRef};
+
+pub trait Optimizer {
+    // Moves every parameter against its gradient
+    fn step(&mut self, tensor_context: &mut TensorContext, parameters: &[TensorRef]);
+    fn learning_rate(&self) -> f64;
+    fn set_learning_rate(&mut self, learning_rate: f64);
+}
+
+// Returns the data of a parameter and its gradient in parts, each with the index it starts at. A
+// gradient kept by row gives one part per row an embedding looked up, so the other rows keep both
+// their data and their optimizer state. A missing gradient is treated as zero.
+fn data_and_grads(
+    tensor_context: &TensorContext,
+    parameter: TensorRef,
+) -> (Vec<f64>, Vec<(usize, Vec<f64>)>) {
+    let data = tensor_context.get_data(parameter);
+    let grads = match tensor_context.row_grads(parameter) {
+        Some(rows) => {
+            let dim = tensor_context.get_shape(parameter)[1];
+            rows.iter().map(|(row, grad)| (row * dim, grad.clone())).collect()
+        }
+        None => {
+            let grad = tensor_context.get_tensor(parameter).grad;
+            vec![(0, grad.unwrap_or_else(|| vec![0.0; data.len()]))]
+        }
+    };
+    (data, grads)
+}
+
+pub struct SGD {
+    pub learning_rate: f64,
+}
+
+impl SGD {
+    pub fn new(learning_rate: f64) -> SGD {
+        SGD { learning_rate }
+    }
+}
+
+impl Default for SGD {
+    fn default() -> Self {
+        SGD::new(0.01)
+    }
+}
+
+impl Optimizer for SGD {
+    fn step(&mut self, tensor_context: &mut TensorContext, parameters: &[TensorRef]) {
+        for parameter in parameters {
+            tensor_context.update_data_from_grad(*parameter, -self.learning_rate);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+pub struct Momentum {
+    pub learning_rate: f64,
+    pub momentum: f64,
+    velocities: HashMap<TensorRef, Vec<f64>>,
+}
+
+impl Momentum {
+    pub fn new(learning_rate: f64, momentum: f64) -> Momentum {
+        Momentum {
+            learning_rate,
+            momentum,
+            velocities: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Momentum {
+    fn default() -> Self {
+        Momentum::new(0.01, 0.9)
+    }
+}
+
+impl Optimizer for Momentum {
+    fn step(&mut self, tensor_context: &mut TensorContext, parameters: &[TensorRef]) {
+        for parameter in parameters {
+            let (mut data, grads) = data_and_grads(tensor_context, *parameter);
+            let velocity = self
+                .velocities
+                .entry(*parameter)
+                .or_insert_with(|| vec![0.0; data.len()]);
+
+            for (start, grad) in grads {
+                let range = start..start + grad.len();
+                for ((value, velocity), grad) in data[range.clone()]
+                    .iter_mut()
+                    .zip(velocity[range].iter_mut())
+                    .zip(grad.iter())
+                {
+                    *velocity = self.momentum * *velocity - self.learning_rate * grad;
+                    *value += *velocity;
+                }
+            }
+            tensor_context.set_data(*parameter, data);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+pub struct AdaGrad {
+    pub learning_rate: f64,
+    pub epsilon: f64,
+    squared_grad_sums: HashMap<TensorRef, Vec<f64>>,
+}
+
+impl AdaGrad {
+    pub fn new(learning_rate: f64, epsilon: f64) -> AdaGrad {
+        AdaGrad {
+            learning_rate,
+            epsilon,
+            squared_grad_sums: HashMap::new(),
+        }
+    }
+}
+
+impl Default for AdaGrad {
+    fn default() -> Self {
+        AdaGrad::new(0.01, 1e-7)
+    }
+}
+
+impl Optimizer for AdaGrad {
+    fn step(&mut self, tensor_context: &mut TensorContext, parameters: &[TensorRef]) {
+        for parameter in parameters {
+            let (mut data, grads) = data_and_grads(tensor_context, *parameter);
+            let squared_grad_sum = self
+                .squared_grad_sums
+                .entry(*parameter)
+                .or_insert_with(|| vec![0.0; data.len()]);
+
+            for (start, grad) in grads {
+                let range = start..start + grad.len();
+                for ((value, sum), grad) in data[range.clone()]
+                    .iter_mut()
+                    .zip(squared_grad_sum[range].iter_mut())
+                    .zip(grad.iter())
+                {
+                    *sum += grad * grad;
+                    *value -= self.learning_rate * grad / (sum.sqrt() + self.epsilon);
+                }
+            }
+            tensor_context.set_data(*parameter, data);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+pub struct RMSprop {
+    pub learning_rate: f64,
+    // Decay rate of the moving average of squared gradients
+    pub rho: f64,
+    pub epsilon: f64,
+    squared_grad_averages: HashMap<TensorRef, Vec<f64>>,
+}
+
+impl RMSprop {
+    pub fn new(learning_rate: f64, rho: f64, epsilon: f64) -> RMSprop {
+        RMSprop {
+            learning_rate,
+            rho,
+            epsilon,
+            squared_grad_averages: HashMap::new(),
+        }
+    }
+}
+
+impl Default for RMSprop {
+    fn default() -> Self {
+        RMSprop::new(0.001, 0.9, 1e-7)
+    }
+}
+
+impl Optimizer for RMSprop {
+    fn step(&mut self, tensor_context: &mut TensorContext, parameters: &[TensorRef]) {
+        for parameter in parameters {
+            let (mut data, grads) = data_and_grads(tensor_context, *parameter);
+            let average = self
+                .squared_grad_averages
+                .entry(*parameter)
+                .or_insert_with(|| vec![0.0; data.len()]);
+
+            for (start, grad) in grads {
+                let range = start..start + grad.len();
+                for ((value, average), grad) in data[range.clone()]
+                    .iter_mut()
+                    .zip(average[range].iter_mut())
+                    .zip(grad.iter())
+                {
+                    *average = self.rho * *average + (1.0 - self.rho) * grad * grad;
+                    *value -= self.learning_rate * grad / (average.sqrt() + self.epsilon);
+                }
+            }
+            tensor_context.set_data(*parameter, data);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+pub struct Adam {
+    pub learning_rate: f64,
+    pub beta1: f64,
+    pub beta2: f64,
+    pub epsilon: f64,
+    steps: i32,
+    first_moments: HashMap<TensorRef, Vec<f64>>,
+    second_moments: HashMap<TensorRef, Vec<f64>>,
+}
+
+impl Adam {
+    pub fn new(learning_rate: f64, beta1: f64, beta2: f64, epsilon: f64) -> Adam {
+        Adam {
+            learning_rate,
+            beta1,
+            beta2,
+            epsilon,
+            steps: 0,
+            first_moments: HashMap::new(),
+            second_moments: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Adam {
+    fn default() -> Self {
+        Adam::new(0.001, 0.9, 0.999, 1e-7)
+    }
+}
+
+impl Optimizer for Adam {
+    fn step(&mut self, tensor_context: &mut TensorContext, parameters: &[TensorRef]) {
+        self.steps += 1;
+        // Both moments start at zero, so early averages are scaled up to remove that bias
+        let first_correction = 1.0 - self.beta1.powi(self.steps);
+        let second_correction = 1.0 - self.beta2.powi(self.steps);
+
+        for parameter in parameters {
+            let (mut data, grads) = data_and_grads(tensor_context, *parameter);
+            let first_moment = self
+                .first_moments
+                .entry(*parameter)
+                .or_insert_with(|| vec![0.0; data.len()]);
+            let second_moment = self
+                .second_moments
+                .entry(*parameter)
+                .or_insert_with(|| vec![0.0; data.len()]);
+
+            for (start, grad) in grads {
+                let range = start..start + grad.len();
+                for (((value, first), second), grad) in data[range.clone()]
+                    .iter_mut()
+                    .zip(first_moment[range.clone()].iter_mut())
+                    .zip(second_moment[range].iter_mut())
+                    .zip(grad.iter())
+                {
+                    *first = self.beta1 * *first + (1.0 - self.beta1) * grad;
+                    *second = self.beta2 * *second + (1.0 - self.beta2) * grad * grad;
+                    let first_estimate = *first / first_correction;
+                    let second_estimate = *second / second_correction;
+                    *value -= self.learning_rate * first_estimate / (second_estimate.sqrt() + self.epsilon);
+                }
+            }
+            tensor_context.set_data(*parameter, data);
+        }
+    }
+
+    fn learning_rate(&self) -> f64 {
+        self.learning_rate
+    }
+
+    fn set_learning_rate(&mut self, learning_rate: f64) {
+        self.learning_rate = learning_rate;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::create_tensor_context;
+
+    use super::*;
+
+    // Takes `steps` optimizer steps on a single parameter that always has the given gradient
+    fn run(optimizer: &mut dyn Optimizer, data: Vec<f64>, grad: Vec<f64>, steps: usize) -> Vec<f64> {
+        let tensor_context = create_tensor_context!(4);
+        let parameter = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![data.len()], data);
+        for _ in 0..steps {
+            let mut tensor_context = tensor_context.borrow_mut();
+            tensor_context.set_grad(parameter, grad.clone());
+            optimizer.step(&mut tensor_context, &[parameter]);
+        }
+        let data = tensor_context.borrow().get_tensor(parameter).data;
+        data
+    }
+
+    fn assert_close(actual: Vec<f64>, expected: Vec<f64>) {
+        actual
+            .iter()
+            .zip(expected.iter())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-9, "{:?} != {:?}", actual, expected));
+    }
+
+    #[test]
+    fn test_sgd() {
+        let data = run(&mut SGD::new(0.1), vec![1.0, 2.0], vec![1.0, -2.0], 2);
+        assert_close(data, vec![0.8, 2.4]);
+    }
+
+    #[test]
+    fn test_momentum_accumulates_velocity() {
+        // Velocities: -0.1 then 0.9 * -0.1 - 0.1 = -0.19
+        let data = run(&mut Momentum::new(0.1, 0.9), vec![1.0], vec![1.0], 2);
+        assert_close(data, vec![0.71]);
+    }
+
+    #[test]
+    fn test_adagrad() {
+        // Accumulated squared gradients are 4 then 8
+        let data = run(&mut AdaGrad::new(0.1, 0.0), vec![1.0], vec![2.0], 2);
+        assert_close(data, vec![1.0 - 0.1 - 0.2 / 8.0_f64.sqrt()]);
+    }
+
+    #[test]
+    fn test_rmsprop() {
+        let data = run(&mut RMSprop::new(0.1, 0.5, 0.0), vec![1.0], vec![2.0], 1);
+        assert_close(data, vec![1.0 - 0.1 * 2.0 / 2.0_f64.sqrt()]);
+    }
+
+    #[test]
+    fn test_adam_bias_correction() {
+        // With bias correction every step moves by the learning rate while the gradient is constant
+        let data = run(&mut Adam::new(0.01, 0.9, 0.999, 0.0), vec![1.0, 1.0], vec![3.0, -0.5], 3);
+        assert_close(data, vec![0.97, 1.03]);
+    }
+
+    #[test]
+    fn test_adam_leaves_rows_not_looked_up() {
+        let tensor_context = create_tensor_context!(16);
+        let mut optimizer = Adam::new(0.1, 0.9, 0.999, 1e-7);
+        let table = tensor_context.borrow_mut().new_tensor(vec![3, 2], vec![1.0; 6]);
+        for token in [0.0, 2.0] {
+            let mut tensor_context = tensor_context.borrow_mut();
+            let indices = tensor_context.new_tensor(vec![1], vec![token]);
+            let rows = tensor_context.embedding(table, indices, None);
+            let loss = tensor_context.sum(rows);
+            tensor_context.backwards(loss);
+            optimizer.step(&mut tensor_context, &[table]);
+            tensor_context.reset_grads(loss);
+        }
+        // Row 0 only moved on the step it was looked up, and row 1 never moved
+        let data = tensor_context.borrow().get_tensor(table).data;
+        assert!((data[0] - 0.9).abs() < 1e-6, "{:?}", data);
+        assert_eq!(data[0], data[1]);
+        assert_eq!(data[2..4].to_vec(), vec![1.0, 1.0]);
+        assert!(data[4] < 1.0);
+    }
+
+    #[test]
+    fn test_set_learning_rate() {
+        let mut optimizer = Adam::default();
+        optimizer.set_learning_rate(0.5);
+        assert_eq!(optimizer.learning_rate(), 0.5);
+    }
+}
@@ -2,4 +2,12 @@ pub mod layers;
 pub mod dense;
 pub mod dropout;
 pub mod flatten;
-pub mod input;
\ No newline at end of file
+pub mod input;
+pub mod conv2d;
+pub mod max_pool2d;
+pub mod avg_pool2d;
+pub mod global_average_pool;
+pub mod batch_norm;
+pub mod layer_norm;
+pub mod rms_norm;
+pub mod embedding;
\ No newline at end of file
@@ -0,0 +1,69 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::math::{
+    im2col::Window,
+    tensor_context::{TensorContext, TensorRef},
+};
+
+use super::layers::layers::Layer;
+
+// Averages the values under every position of a window over each channel of an NCHW tensor.
+// Padding is left out of the average.
+pub struct AvgPool2D {
+    window: Window,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for AvgPool2D {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        self.tensor_context.borrow_mut().recompute(self.output_tensor.unwrap());
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        self.output_tensor = Some(self.tensor_context.borrow_mut().avg_pool(input, self.window));
+        self.output_tensor.unwrap()
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        vec![]
+    }
+}
+
+impl AvgPool2D {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, window: Window) -> AvgPool2D {
+        AvgPool2D {
+            window,
+            tensor_context,
+            output_tensor: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_tensor_context, math::im2col::Padding};
+
+    use super::*;
+
+    #[test]
+    fn test_forward() {
+        let tensor_context = create_tensor_context!(10);
+        let window = Window {
+            padding: Padding::Same,
+            ..Window::new((2, 2))
+        };
+        let mut pool = AvgPool2D::new(tensor_context.clone(), window);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![1, 1, 2, 2], vec![1.0, 2.0, 3.0, 4.0]);
+        pool.compile(input);
+        let output = pool.forward(input);
+
+        // Same padding adds a row and column after the input
+        let output_tensor = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output_tensor.shape, vec![1, 1, 2, 2]);
+        assert_eq!(output_tensor.data, vec![2.5, 3.0, 3.5, 4.0]);
+    }
+}
@@ -0,0 +1,240 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::math::tensor_context::{TensorContext, TensorRef};
+
+use super::layers::layers::Layer;
+
+// Normalizes every channel, the second axis, to zero mean and unit variance before scaling by
+// gamma and shifting by beta. Works on the [batch, features] output of Dense as well as on
+// [batch, channels, height, width] feature maps. Training normalizes with the statistics of the
+// batch and folds them into running statistics, which inference then uses instead.
+pub struct BatchNorm {
+    // Weight of the old running statistics in every update, between 0 and 1: running = momentum *
+    // running + (1 - momentum) * batch. This is the Keras convention and the reverse of PyTorch,
+    // where momentum weighs the new batch, so 0.99 here corresponds to 0.01 there.
+    pub momentum: f64,
+    // Added to the variance before taking its square root, at least 0
+    pub epsilon: f64,
+    // Set by the model: on in fit, off in evaluate and predict
+    pub training: bool,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    gamma: Option<TensorRef>,
+    beta: Option<TensorRef>,
+    running_mean: Option<TensorRef>,
+    running_variance: Option<TensorRef>,
+    input_tensor: Option<TensorRef>,
+    moments: Option<TensorRef>,
+    // Tensors after the moments, in the order they need recomputing on a forward pass
+    forward_tensors: Vec<TensorRef>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for BatchNorm {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        let moments = self.moments.unwrap();
+        if self.training {
+            tensor_context.recompute(moments);
+            self.update_running_statistics(&mut tensor_context);
+        } else {
+            let mut running_moments = tensor_context.get_tensor(self.running_mean.unwrap()).data;
+            running_moments.extend(tensor_context.get_tensor(self.running_variance.unwrap()).data);
+            tensor_context.set_data(moments, running_moments);
+        }
+
+        for tensor in self.forward_tensors.iter() {
+            tensor_context.recompute(*tensor);
+        }
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        let shape = self.tensor_context.borrow().get_shape(input);
+        if shape.len() < 2 {
+            panic!("BatchNorm expects channels on the second axis, got shape {:?}", shape);
+        }
+        let channels = shape[1];
+
+        let needs_parameters = match self.gamma {
+            Some(gamma) => self.tensor_context.borrow().get_shape(gamma)[0] != channels,
+            None => true,
+        };
+        if needs_parameters {
+            self.initialize_parameters(&shape);
+        }
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        let moments = tensor_context.channel_moments(input);
+        let normalized = tensor_context.normalize(input, moments, self.epsilon);
+        let scaled = tensor_context.mul(normalized, self.gamma.unwrap());
+        let output = tensor_context.add(scaled, self.beta.unwrap());
+        self.input_tensor = Some(input);
+        self.moments = Some(moments);
+        self.forward_tensors = vec![normalized, scaled, output];
+        self.output_tensor = Some(output);
+        output
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        vec![self.gamma.unwrap(), self.beta.unwrap()]
+    }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
+}
+
+impl BatchNorm {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, momentum: f64, epsilon: f64) -> BatchNorm {
+        if !(0.0..=1.0).contains(&momentum) {
+            panic!("BatchNorm momentum must be between 0 and 1, got {}", momentum);
+        }
+        if epsilon.is_nan() || epsilon < 0.0 {
+            panic!("BatchNorm epsilon must be at least 0, got {}", epsilon);
+        }
+        BatchNorm {
+            momentum,
+            epsilon,
+            training: false,
+            tensor_context,
+            gamma: None,
+            beta: None,
+            running_mean: None,
+            running_variance: None,
+            input_tensor: None,
+            moments: None,
+            forward_tensors: vec![],
+            output_tensor: None,
+        }
+    }
+
+    pub fn running_mean(&self) -> Vec<f64> {
+        self.tensor_context.borrow().get_tensor(self.running_mean.unwrap()).data
+    }
+
+    pub fn running_variance(&self) -> Vec<f64> {
+        self.tensor_context.borrow().get_tensor(self.running_variance.unwrap()).data
+    }
+
+    // Moves the running statistics towards the moments of the current batch
+    fn update_running_statistics(&self, tensor_context: &mut TensorContext) {
+        let moments = tensor_context.get_tensor(self.moments.unwrap()).data;
+        let channels = moments.len() / 2;
+        let count = tensor_context.get_tensor(self.input_tensor.unwrap()).data.len() / channels;
+        // The running variance is an unbiased estimate
+        let correction = if count > 1 { count as f64 / (count - 1) as f64 } else { 1.0 };
+
+        let update = |running: TensorRef, batch: &[f64], tensor_context: &mut TensorContext| {
+            let data = tensor_context
+                .get_tensor(running)
+                .data
+                .iter()
+                .zip(batch.iter())
+                .map(|(running, batch)| self.momentum * running + (1.0 - self.momentum) * batch)
+                .collect();
+            tensor_context.set_data(running, data);
+        };
+        let variances: Vec<f64> = moments[channels..].iter().map(|a| a * correction).collect();
+        update(self.running_mean.unwrap(), &moments[..channels], tensor_context);
+        update(self.running_variance.unwrap(), &variances, tensor_context);
+    }
+
+    // Gamma of one and beta of zero, shaped to broadcast along every axis after the channels.
+    // The running statistics start at zero mean and unit variance.
+    fn initialize_parameters(&mut self, shape: &[usize]) {
+        let channels = shape[1];
+        let mut parameter_shape = vec![channels];
+        parameter_shape.extend(vec![1; shape.len() - 2]);
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        self.gamma = Some(tensor_context.new_tensor(parameter_shape.clone(), vec![1.0; channels]));
+        self.beta = Some(tensor_context.new_tensor(parameter_shape, vec![0.0; channels]));
+        self.running_mean = Some(tensor_context.new_tensor(vec![channels], vec![0.0; channels]));
+        self.running_variance = Some(tensor_context.new_tensor(vec![channels], vec![1.0; channels]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_tensor_context, math::gradcheck::gradcheck};
+
+    use super::*;
+
+    #[test]
+    fn test_training_normalizes_batch() {
+        let tensor_context = create_tensor_context!(1024);
+        let mut batch_norm = BatchNorm::new(tensor_context.clone(), 0.9, 0.0);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![2, 2], vec![1.0, 2.0, 3.0, 10.0]);
+        let output = batch_norm.compile(input);
+        batch_norm.set_training(true);
+        batch_norm.forward(input);
+
+        assert_eq!(tensor_context.borrow().get_tensor(output).data, vec![-1.0, -1.0, 1.0, 1.0]);
+        // Batch means of 2 and 6, and unbiased variances of 2 and 32
+        let running_mean = batch_norm.running_mean();
+        assert!((running_mean[0] - 0.2).abs() < 1e-12);
+        assert!((running_mean[1] - 0.6).abs() < 1e-12);
+        let running_variance = batch_norm.running_variance();
+        assert!((running_variance[0] - 1.1).abs() < 1e-12);
+        assert!((running_variance[1] - 4.1).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_inference_uses_running_statistics() {
+        let tensor_context = create_tensor_context!(1024);
+        let mut batch_norm = BatchNorm::new(tensor_context.clone(), 0.0, 0.0);
+        let data: Vec<f64> = (0..2 * 3 * 2 * 2).map(|a| a as f64).collect();
+        let input = tensor_context.borrow_mut().new_tensor(vec![2, 3, 2, 2], data);
+        let output = batch_norm.compile(input);
+        assert_eq!(tensor_context.borrow().get_shape(batch_norm.get_parameters()[0]), vec![3, 1, 1]);
+
+        // With no momentum the running statistics are those of the last batch
+        batch_norm.set_training(true);
+        batch_norm.forward(input);
+        let training_output = tensor_context.borrow().get_tensor(output).data;
+        assert_eq!(batch_norm.running_mean(), vec![7.5, 11.5, 15.5]);
+
+        // Inference on a single sample now shifts by the running mean rather than its own mean
+        batch_norm.set_training(false);
+        tensor_context.borrow_mut().set_shape(input, vec![1, 3, 2, 2]);
+        tensor_context.borrow_mut().set_data(input, (0..12).map(|a| a as f64).collect());
+        batch_norm.forward(input);
+        let inference_output = tensor_context.borrow().get_tensor(output).data;
+        assert_eq!(inference_output.len(), 12);
+        let standard_deviation = batch_norm.running_variance()[0].sqrt();
+        assert!((inference_output[0] - (0.0 - 7.5) / standard_deviation).abs() < 1e-12);
+        assert_ne!(inference_output[..], training_output[..12]);
+    }
+
+    #[test]
+    fn test_gradcheck_batch_norm() {
+        let data: Vec<f64> = (0..4 * 3).map(|a| (a as f64 * 1.7).sin()).collect();
+        let report = gradcheck(
+            vec![(vec![4, 3], data), (vec![3], vec![0.5, 1.5, -1.0]), (vec![3], vec![0.1, 0.2, 0.3])],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                let moments = tensor_context.channel_moments(inputs[0]);
+                let normalized = tensor_context.normalize(inputs[0], moments, 1e-5);
+                let scaled = tensor_context.mul(normalized, inputs[1]);
+                let shifted = tensor_context.add(scaled, inputs[2]);
+                let squared = tensor_context.mul(shifted, shifted);
+                let cubed = tensor_context.mul(squared, shifted);
+                tensor_context.sum(cubed)
+            },
+        );
+        assert!(report.passed(1e-5), "{:?}", report.failures(1e-5));
+    }
+    #[test]
+    #[should_panic(expected = "BatchNorm momentum must be between 0 and 1")]
+    fn test_momentum_out_of_range() {
+        BatchNorm::new(create_tensor_context!(16), 1.5, 1e-3);
+    }
+
+    #[test]
+    #[should_panic(expected = "BatchNorm epsilon must be at least 0")]
+    fn test_negative_epsilon() {
+        BatchNorm::new(create_tensor_context!(16), 0.9, -1e-3);
+    }
+}
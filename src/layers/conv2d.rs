@@ -0,0 +1,262 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use rand::Rng;
+
+use crate::layers::layers::layers::Layer;
+
+use crate::math::im2col::{Window, WindowDims};
+use crate::math::tensor_context::{TensorContext, TensorRef};
+use crate::nuerons::activation_function::ActivationFunction;
+
+// 2-D convolution over NCHW tensors. Every window of the input is unrolled into a column with
+// im2col, so the convolution is a single matrix product with the filters.
+pub struct Conv2D {
+    filters: usize,
+    window: Window,
+    use_bias: bool,
+    activation_function: ActivationFunction,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    // [filters, channels, kernel height, kernel width]
+    weights: Option<TensorRef>,
+    // [filters, 1], broadcast over every window position
+    bias: Option<TensorRef>,
+    // Tensors recorded by compile, in the order they need recomputing on a forward pass
+    forward_tensors: Vec<TensorRef>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for Conv2D {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        for tensor in self.forward_tensors.iter() {
+            tensor_context.recompute(*tensor);
+        }
+
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        let input_shape = self.tensor_context.borrow().get_shape(input);
+        let dims = WindowDims::new(&input_shape, &self.window);
+
+        let needs_weights = match self.weights {
+            Some(weights) => self.tensor_context.borrow().get_shape(weights)[1] != dims.channels,
+            None => true,
+        };
+        if needs_weights {
+            self.initialize_parameters(dims.channels);
+        }
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        self.forward_tensors.clear();
+
+        // [filters, patch] · [batch, patch, positions] gives [batch, filters, positions]
+        let columns = tensor_context.im2col(input, self.window);
+        let filters = tensor_context.reshape(self.weights.unwrap(), vec![self.filters, dims.patch_size()]);
+        let mut output = tensor_context.matmul(filters, columns);
+        self.forward_tensors.extend([columns, filters, output]);
+        if self.use_bias {
+            output = tensor_context.add(output, self.bias.unwrap());
+            self.forward_tensors.push(output);
+        }
+
+        // The leading axis follows the batch size of the input
+        output = tensor_context.reshape_samples(
+            output,
+            vec![self.filters, dims.output_height, dims.output_width],
+        );
+        self.forward_tensors.push(output);
+
+        // Applied to the NCHW output, so an activation over an axis sees the layer's own axes
+        output = tensor_context.apply(self.activation_function, output);
+        self.forward_tensors.push(output);
+
+        self.output_tensor = Some(output);
+        output
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        if self.use_bias {
+            vec![self.weights.unwrap(), self.bias.unwrap()]
+        } else {
+            vec![self.weights.unwrap()]
+        }
+    }
+}
+
+impl Conv2D {
+    pub fn new(
+        tensor_context: Rc<RefCell<TensorContext>>,
+        filters: usize,
+        window: Window,
+        activation_function: ActivationFunction,
+        use_bias: bool,
+    ) -> Conv2D {
+        Conv2D {
+            filters,
+            window,
+            use_bias,
+            activation_function,
+            tensor_context,
+            weights: None,
+            bias: None,
+            forward_tensors: Vec::new(),
+            output_tensor: None,
+        }
+    }
+
+    // Glorot uniform weights and zero bias
+    fn initialize_parameters(&mut self, channels: usize) {
+        let (kernel_height, kernel_width) = self.window.kernel;
+        let kernel_size = kernel_height * kernel_width;
+        let limit = (6.0 / ((channels + self.filters) * kernel_size) as f64).sqrt();
+        let mut rng = rand::thread_rng();
+        let weights = (0..self.filters * channels * kernel_size)
+            .map(|_| rng.gen_range(-limit..limit))
+            .collect();
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        self.weights = Some(tensor_context.new_tensor(
+            vec![self.filters, channels, kernel_height, kernel_width],
+            weights,
+        ));
+        self.bias = Some(tensor_context.new_tensor(vec![self.filters, 1], vec![0.0; self.filters]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_tensor_context, math::im2col::Padding};
+
+    use super::*;
+
+    // Direct convolution of one sample with a single filter and no bias
+    fn convolve(input: &[f64], shape: [usize; 3], weights: &[f64], window: &Window) -> Vec<f64> {
+        let [channels, height, width] = shape;
+        let dims = WindowDims::new(&[1, channels, height, width], window);
+        let (kernel_height, kernel_width) = window.kernel;
+        let mut output = vec![];
+        for y in 0..dims.output_height {
+            for x in 0..dims.output_width {
+                let mut sum = 0.0;
+                for channel in 0..channels {
+                    for i in 0..kernel_height {
+                        for j in 0..kernel_width {
+                            if let Some(offset) = dims.input_offset(y, x, i, j) {
+                                let weight = weights[(channel * kernel_height + i) * kernel_width + j];
+                                sum += weight * input[channel * height * width + offset];
+                            }
+                        }
+                    }
+                }
+                output.push(sum);
+            }
+        }
+        output
+    }
+
+    #[test]
+    fn test_forward() {
+        let tensor_context = create_tensor_context!(1024);
+        let window = Window {
+            stride: (2, 2),
+            padding: Padding::Same,
+            ..Window::new((3, 3))
+        };
+        let mut conv = Conv2D::new(tensor_context.clone(), 2, window, ActivationFunction::Tanh, true);
+        let data: Vec<f64> = (0..2 * 2 * 5 * 5).map(|a| (a as f64 * 0.3).sin()).collect();
+        let input = tensor_context.borrow_mut().new_tensor(vec![2, 2, 5, 5], data.clone());
+        let output = conv.compile(input);
+
+        let parameters = conv.get_parameters();
+        let weights: Vec<f64> = (0..2 * 2 * 3 * 3).map(|a| (a as f64 * 0.7).cos() / 4.0).collect();
+        tensor_context.borrow_mut().set_data(parameters[0], weights.clone());
+        tensor_context.borrow_mut().set_data(parameters[1], vec![0.5, -0.5]);
+        conv.forward(input);
+
+        let output = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output.shape, vec![2, 2, 3, 3]);
+        let mut expected = vec![];
+        for sample in 0..2 {
+            let sample_data = &data[sample * 50..(sample + 1) * 50];
+            for (filter, bias) in [0.5, -0.5].iter().enumerate() {
+                let filter_weights = &weights[filter * 18..(filter + 1) * 18];
+                let convolved = convolve(sample_data, [2, 5, 5], filter_weights, &window);
+                expected.extend(convolved.iter().map(|a| (a + bias).tanh()));
+            }
+        }
+        for (actual, expected) in output.data.iter().zip(expected.iter()) {
+            assert!((actual - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_softmax_over_output_axes() {
+        let tensor_context = create_tensor_context!(1024);
+        let mut conv = Conv2D::new(
+            tensor_context.clone(),
+            3,
+            Window::new((2, 2)),
+            ActivationFunction::Softmax(-1),
+            true,
+        );
+        let data: Vec<f64> = (0..2 * 4 * 5).map(|a| (a as f64 * 0.4).sin()).collect();
+        let input = tensor_context.borrow_mut().new_tensor(vec![1, 2, 4, 5], data);
+        let output = conv.compile(input);
+        conv.forward(input);
+
+        // Every row of the [batch, filters, height, width] output sums to one
+        let output = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output.shape, vec![1, 3, 3, 4]);
+        for row in output.data.chunks(4) {
+            assert!((row.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_batch_size_follows_input() {
+        let tensor_context = create_tensor_context!(1024);
+        let mut conv = Conv2D::new(
+            tensor_context.clone(),
+            4,
+            Window::new((2, 2)),
+            ActivationFunction::ReLU,
+            false,
+        );
+        let input = tensor_context.borrow_mut().new_tensor(vec![1, 3, 4, 4], vec![1.0; 48]);
+        conv.compile(input);
+        assert_eq!(conv.get_parameters().len(), 1);
+
+        tensor_context.borrow_mut().set_shape(input, vec![5, 3, 4, 4]);
+        tensor_context.borrow_mut().set_data(input, vec![1.0; 240]);
+        let output = conv.forward(input);
+        assert_eq!(tensor_context.borrow().get_shape(output), vec![5, 4, 3, 3]);
+    }
+
+    #[test]
+    fn test_backwards_reaches_parameters_and_input() {
+        let tensor_context = create_tensor_context!(1024);
+        let window = Window {
+            dilation: (2, 2),
+            ..Window::new((2, 2))
+        };
+        let mut conv = Conv2D::new(tensor_context.clone(), 1, window, ActivationFunction::Tanh, true);
+        let data: Vec<f64> = (0..16).map(|a| a as f64 / 16.0).collect();
+        let input = tensor_context.borrow_mut().new_tensor(vec![1, 1, 4, 4], data);
+        let output = conv.compile(input);
+        conv.forward(input);
+
+        let loss = tensor_context.borrow_mut().sum(output);
+        tensor_context.borrow_mut().backwards(loss);
+
+        let parameters = conv.get_parameters();
+        let context = tensor_context.borrow();
+        assert_eq!(context.get_tensor(parameters[0]).grad.unwrap().len(), 4);
+        assert_eq!(context.get_tensor(parameters[1]).grad.unwrap().len(), 1);
+        // With a dilation of 2 the corners of the input fall under exactly one window position
+        let input_grad = context.get_tensor(input).grad.unwrap();
+        assert_eq!(input_grad.len(), 16);
+        assert_ne!(input_grad[0], 0.0);
+    }
+}
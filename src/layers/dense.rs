@@ -1,61 +1,77 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use rand::Rng;
+
 use crate::layers::layers::layers::Layer;
 
 use crate::math::tensor_context::{TensorContext, TensorRef};
 use crate::nuerons::activation_function::ActivationFunction;
-use crate::nuerons::nuerons::Neuron;
 
 pub struct Dense {
-    pub neurons: Vec<Neuron>,
     size: usize,
     activation_function: ActivationFunction,
     tensor_context: Rc<RefCell<TensorContext>>,
+    weights: Option<TensorRef>,
+    bias: Option<TensorRef>,
+    // Tensors recorded by compile, in the order they need recomputing on a forward pass
+    forward_tensors: Vec<TensorRef>,
     output_tensor: Option<TensorRef>,
     input_tensor: Option<TensorRef>,
 }
 
 impl Layer for Dense {
-    fn forward(&self, input: TensorRef) -> TensorRef {
-        let feed_forward_results: Vec<TensorRef> = self
-            .neurons
-            .iter()
-            .map(|neuron| neuron.feed_forward(input))
-            .collect();
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        for tensor in self.forward_tensors.iter() {
+            tensor_context.recompute(*tensor);
+        }
 
-        self.tensor_context
-            .borrow_mut()
-            .concat_inplace(feed_forward_results, self.output_tensor.unwrap());
-        
         self.output_tensor.unwrap()
     }
 
     fn compile(&mut self, input: TensorRef) -> TensorRef {
         self.input_tensor = Some(input);
-        let input_size = self.tensor_context.borrow().get_tensor(input).shape[0];
-
-        for _ in 0..self.size {
-            self.neurons.push(Neuron::new(self.tensor_context.clone(), input_size, self.activation_function));
+        let input_shape = self.tensor_context.borrow().get_tensor(input).shape;
+        let input_size = *input_shape.last().unwrap();
+
+        let needs_weights = match self.weights {
+            Some(weights) => self.tensor_context.borrow().get_tensor(weights).shape[0] != input_size,
+            None => true,
+        };
+        if needs_weights {
+            self.initialize_parameters(input_size);
         }
 
-        let initialize_results: Vec<TensorRef> = self
-            .neurons
-            .iter_mut()
-            .map(|neuron| neuron.initialize(input))
-            .collect();
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        self.forward_tensors.clear();
+
+        // A single sample is treated as a batch of one so it can go through the same matrix product
+        let unbatched = input_shape.len() == 1;
+        let batch = if unbatched {
+            let batch = tensor_context.reshape(input, vec![1, input_size]);
+            self.forward_tensors.push(batch);
+            batch
+        } else {
+            input
+        };
+
+        let product = tensor_context.matmul(batch, self.weights.unwrap());
+        let biased = tensor_context.add(product, self.bias.unwrap());
+        let mut output = tensor_context.apply(self.activation_function, biased);
+        self.forward_tensors.extend([product, biased, output]);
+
+        if unbatched {
+            output = tensor_context.reshape(output, vec![self.size]);
+            self.forward_tensors.push(output);
+        }
 
-        self.output_tensor = Some(self.tensor_context.borrow_mut().concat(initialize_results));
-        self.output_tensor.unwrap()
+        self.output_tensor = Some(output);
+        output
     }
 
     fn get_parameters(&self) -> Vec<TensorRef> {
-        let mut parameters = Vec::new();
-        for neuron in self.neurons.iter() {
-            parameters.append(&mut neuron.get_parameters());
-        }
-        parameters
-    
+        vec![self.weights.unwrap(), self.bias.unwrap()]
     }
 }
 
@@ -65,17 +81,30 @@ impl Dense {
         n: usize,
         activation_function: ActivationFunction,
     ) -> Dense {
-        let neurons = Vec::new();
-
         Dense {
-            neurons,
             tensor_context,
             activation_function,
             size: n,
+            weights: None,
+            bias: None,
+            forward_tensors: Vec::new(),
             output_tensor: None,
             input_tensor: None,
         }
     }
+
+    // Glorot uniform weights and zero bias
+    fn initialize_parameters(&mut self, input_size: usize) {
+        let limit = (6.0 / (input_size + self.size) as f64).sqrt();
+        let mut rng = rand::thread_rng();
+        let weights = (0..input_size * self.size)
+            .map(|_| rng.gen_range(-limit..limit))
+            .collect();
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        self.weights = Some(tensor_context.new_tensor(vec![input_size, self.size], weights));
+        self.bias = Some(tensor_context.new_tensor(vec![self.size], vec![0.0; self.size]));
+    }
 }
 
 #[cfg(test)]
@@ -88,7 +117,7 @@ mod tests {
     fn test_compile() {
         // Create a tensor context
         let tensor_context = create_tensor_context!(1024);
-        
+
         // Create a dense layer
         let mut dense = Dense::new(tensor_context.clone(), 3, ActivationFunction::ReLU);
 
@@ -102,9 +131,19 @@ mod tests {
 
         assert_eq!(tensor_context.borrow_mut().get_tensor(output).shape, vec![3]);
 
+        // Fix the weights so the outputs are known
+        let parameters = dense.get_parameters();
+        tensor_context
+            .borrow_mut()
+            .set_data(parameters[0], vec![1.0, 0.5, -1.0, 0.0, 0.5, 1.0]);
+        tensor_context
+            .borrow_mut()
+            .set_data(parameters[1], vec![0.5, 0.0, 0.0]);
+
         // Check if the output tensor has the correct values
+        dense.forward(input);
         let output_values = tensor_context.borrow_mut().get_tensor(output).data.clone();
-        assert_ne!(output_values, vec![0.0, 0.0, 0.0]);
+        assert_eq!(output_values, vec![1.5, 1.5, 1.0]);
 
         // Check if the output tensor responds to changes in input tensor
         tensor_context.borrow_mut().set_data(input, vec![3.0, 4.0]);
@@ -124,5 +163,55 @@ mod tests {
         let output_values = tensor_context.borrow_mut().get_tensor(output).data.clone();
         assert_ne!(output_values, old_output_values); // This is probably the root of the problem
     }
+
+    #[test]
+    fn test_batch_forward() {
+        let tensor_context = create_tensor_context!(1024);
+        let mut dense = Dense::new(tensor_context.clone(), 2, ActivationFunction::Tanh);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![3, 2], vec![1.0, 2.0, 0.0, -1.0, 0.5, 0.5]);
+        let output = dense.compile(input);
+
+        let parameters = dense.get_parameters();
+        assert_eq!(tensor_context.borrow().get_tensor(parameters[0]).shape, vec![2, 2]);
+        assert_eq!(tensor_context.borrow().get_tensor(parameters[1]).shape, vec![2]);
+        tensor_context
+            .borrow_mut()
+            .set_data(parameters[0], vec![1.0, 0.0, 0.0, 1.0]);
+        tensor_context
+            .borrow_mut()
+            .set_data(parameters[1], vec![0.0, 1.0]);
+        dense.forward(input);
+
+        let output = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output.shape, vec![3, 2]);
+        let expected: Vec<f64> = [1.0, 3.0, 0.0, 0.0, 0.5, 1.5].iter().map(|a: &f64| a.tanh()).collect();
+        assert_eq!(output.data, expected);
+    }
+
+    #[test]
+    fn test_backwards_reaches_parameters() {
+        let tensor_context = create_tensor_context!(1024);
+        let mut dense = Dense::new(tensor_context.clone(), 1, ActivationFunction::ReLU);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![2, 2], vec![1.0, 2.0, 3.0, 4.0]);
+        let output = dense.compile(input);
+
+        let parameters = dense.get_parameters();
+        tensor_context
+            .borrow_mut()
+            .set_data(parameters[0], vec![1.0, 1.0]);
+        dense.forward(input);
+
+        let loss = tensor_context.borrow_mut().sum(output);
+        tensor_context.borrow_mut().backwards(loss);
+
+        let weights = tensor_context.borrow().get_tensor(parameters[0]);
+        let bias = tensor_context.borrow().get_tensor(parameters[1]);
+        assert_eq!(weights.grad, Some(vec![4.0, 6.0]));
+        assert_eq!(bias.grad, Some(vec![2.0]));
+    }
 }
 
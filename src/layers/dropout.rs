@@ -8,40 +8,40 @@ use crate::math::{
 
 use super::layers::layers::Layer;
 
+// Zeroes each element with probability rate while training and scales the rest by 1/(1-rate), so
+// the expected output is the same in both modes and inference passes the input straight through
 pub struct Dropout {
     pub rate: f64,
+    // Set by the model: on in fit, off in evaluate and predict
     pub training: bool,
     tensor_context: Rc<RefCell<TensorContext>>,
     mask_tensor: Option<TensorRef>,
     output_tensor: Option<TensorRef>,
     distribution: Bernoulli,
-    layer_shape: Option<usize>,
 }
 
 impl Layer for Dropout {
     fn forward(&self, input: TensorRef) -> TensorRef {
-        if self.training {
-            let mut mask = vec![0.0; self.layer_shape.unwrap()];
-            for i in 0..self.layer_shape.unwrap() {
-                mask[i] = if self.distribution.sample(&mut rand::thread_rng()) {
-                    1.0
-                } else {
-                    0.0
-                };
-            }
-
-            self.tensor_context
-                .borrow_mut()
-                .set_data(self.mask_tensor.unwrap(), mask);
-        
+        let shape = self.tensor_context.borrow().get_shape(input);
+        let size = shape.iter().product();
+        let mask = if self.training {
+            (0..size)
+                .map(|_| {
+                    if self.distribution.sample(&mut rand::thread_rng()) {
+                        1.0 / (1.0 - self.rate)
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
         } else {
-            self.tensor_context.borrow_mut().set_data(
-                self.mask_tensor.unwrap(),
-                vec![1.0; self.layer_shape.unwrap()],
-            );
-        }
+            vec![1.0; size]
+        };
 
-        self.tensor_context.borrow_mut().mul_inplace(
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        tensor_context.set_shape(self.mask_tensor.unwrap(), shape);
+        tensor_context.set_data(self.mask_tensor.unwrap(), mask);
+        tensor_context.mul_inplace(
             input,
             self.mask_tensor.unwrap(),
             self.output_tensor.unwrap(),
@@ -50,12 +50,12 @@ impl Layer for Dropout {
     }
 
     fn compile(&mut self, input: TensorRef) -> TensorRef {
-        let input_shape = self.tensor_context.borrow().get_tensor(input).shape.clone();
-        self.layer_shape = Some(input_shape.iter().product());
+        let input_shape = self.tensor_context.borrow().get_shape(input);
+        let size = input_shape.iter().product();
         self.mask_tensor = Some(
             self.tensor_context
                 .borrow_mut()
-                .new_tensor(input_shape, vec![0.0; self.layer_shape.unwrap()]),
+                .new_tensor(input_shape, vec![0.0; size]),
         );
         self.output_tensor = Some(
             self.tensor_context
@@ -68,18 +68,67 @@ impl Layer for Dropout {
     fn get_parameters(&self) -> Vec<TensorRef> {
         vec![]
     }
+
+    fn set_training(&mut self, training: bool) {
+        self.training = training;
+    }
 }
 
 impl Dropout {
-    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, rate: f64, training: bool) -> Dropout {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, rate: f64) -> Dropout {
+        if !(0.0..1.0).contains(&rate) {
+            panic!("Dropout rate must be at least 0 and below 1, got {}", rate);
+        }
         Dropout {
             tensor_context,
             rate,
-            training,
+            training: false,
             output_tensor: None,
             mask_tensor: None,
-            distribution: Bernoulli::new(rate).unwrap(),
-            layer_shape: None,
+            // Samples whether an element is kept
+            distribution: Bernoulli::new(1.0 - rate).unwrap(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::create_tensor_context;
+
+    use super::*;
+
+    fn dropout(rate: f64) -> (Rc<RefCell<TensorContext>>, Dropout, TensorRef) {
+        let tensor_context = create_tensor_context!(10);
+        let input = tensor_context.borrow_mut().new_tensor(vec![1000], vec![1.0; 1000]);
+        let mut dropout = Dropout::new(tensor_context.clone(), rate);
+        dropout.compile(input);
+        (tensor_context, dropout, input)
+    }
+
+    #[test]
+    fn test_inference_passes_input_through() {
+        let (tensor_context, dropout, input) = dropout(0.5);
+        let output = dropout.forward(input);
+        assert_eq!(tensor_context.borrow().get_tensor(output).data, vec![1.0; 1000]);
+    }
+
+    #[test]
+    fn test_training_scales_kept_elements() {
+        let (tensor_context, mut dropout, input) = dropout(0.25);
+        dropout.set_training(true);
+        let output = dropout.forward(input);
+        let data = tensor_context.borrow().get_tensor(output).data;
+        assert!(data.iter().all(|x| *x == 0.0 || (x - 4.0 / 3.0).abs() < 1e-12));
+        let dropped = data.iter().filter(|x| **x == 0.0).count();
+        assert!((150..350).contains(&dropped), "dropped {} of 1000", dropped);
+        let mean = data.iter().sum::<f64>() / 1000.0;
+        assert!((mean - 1.0).abs() < 0.15, "mean {}", mean);
+    }
+
+    #[test]
+    #[should_panic(expected = "Dropout rate must be at least 0 and below 1")]
+    fn test_invalid_rate() {
+        let tensor_context = create_tensor_context!(10);
+        Dropout::new(tensor_context, 1.0);
+    }
+}
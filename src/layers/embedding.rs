@@ -0,0 +1,207 @@
+use std::{cell::RefCell, rc::Rc};
+
+use rand::Rng;
+
+use crate::math::{
+    tensor::Tensor,
+    tensor_context::{TensorContext, TensorRef},
+};
+
+use super::layers::layers::Layer;
+
+// Maps integer token indices, stored as floats, to rows of a learnable [vocab, dim] table. The
+// output has the shape of the indices with a dim axis appended. Backpropagation only adds into the
+// rows that were looked up, and never into the padding row, which starts out as zeros.
+pub struct Embedding {
+    vocab: usize,
+    dim: usize,
+    padding_index: Option<usize>,
+    // Whether the table is returned as a parameter for the optimizer to update
+    pub trainable: bool,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    table: TensorRef,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for Embedding {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        self.tensor_context.borrow_mut().recompute(self.output_tensor.unwrap());
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        let output = self
+            .tensor_context
+            .borrow_mut()
+            .embedding(self.table, input, self.padding_index);
+        self.output_tensor = Some(output);
+        output
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        if self.trainable {
+            vec![self.table]
+        } else {
+            vec![]
+        }
+    }
+}
+
+impl Embedding {
+    // A table drawn uniformly from [-0.05, 0.05)
+    pub fn new(
+        tensor_context: Rc<RefCell<TensorContext>>,
+        vocab: usize,
+        dim: usize,
+        padding_index: Option<usize>,
+    ) -> Embedding {
+        let mut rng = rand::thread_rng();
+        let mut table: Vec<f64> = (0..vocab * dim).map(|_| rng.gen_range(-0.05..0.05)).collect();
+        if let Some(padding_index) = padding_index {
+            Embedding::check_padding_index(padding_index, vocab);
+            table[padding_index * dim..(padding_index + 1) * dim].fill(0.0);
+        }
+        let table = tensor_context.borrow_mut().new_tensor(vec![vocab, dim], table);
+        Embedding {
+            vocab,
+            dim,
+            padding_index,
+            trainable: true,
+            tensor_context,
+            table,
+            output_tensor: None,
+        }
+    }
+
+    // Starts from existing vectors, given as a [vocab, dim] tensor. The padding row is kept as
+    // given.
+    pub fn from_pretrained(
+        tensor_context: Rc<RefCell<TensorContext>>,
+        vectors: Tensor,
+        padding_index: Option<usize>,
+        trainable: bool,
+    ) -> Embedding {
+        if vectors.shape.len() != 2 || vectors.shape.iter().product::<usize>() != vectors.data.len() {
+            panic!(
+                "Pretrained vectors must have shape [vocab, dim], got {:?} with {} values",
+                vectors.shape,
+                vectors.data.len()
+            );
+        }
+        let (vocab, dim) = (vectors.shape[0], vectors.shape[1]);
+        if let Some(padding_index) = padding_index {
+            Embedding::check_padding_index(padding_index, vocab);
+        }
+        let table = tensor_context.borrow_mut().new_tensor(vectors.shape, vectors.data);
+        Embedding {
+            vocab,
+            dim,
+            padding_index,
+            trainable,
+            tensor_context,
+            table,
+            output_tensor: None,
+        }
+    }
+
+    pub fn vocab(&self) -> usize {
+        self.vocab
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    // The vector of one token
+    pub fn vector(&self, token: usize) -> Vec<f64> {
+        let table = self.tensor_context.borrow().get_tensor(self.table).data;
+        table[token * self.dim..(token + 1) * self.dim].to_vec()
+    }
+
+    fn check_padding_index(padding_index: usize, vocab: usize) {
+        if padding_index >= vocab {
+            panic!("Padding index {} is outside a vocabulary of {}", padding_index, vocab);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::create_tensor_context;
+
+    use super::*;
+
+    fn pretrained(tensor_context: &Rc<RefCell<TensorContext>>, padding_index: Option<usize>) -> Embedding {
+        let vectors = Tensor::new(vec![3, 2], vec![0.0, 0.0, 1.0, 2.0, 3.0, 4.0]);
+        Embedding::from_pretrained(tensor_context.clone(), vectors, padding_index, true)
+    }
+
+    #[test]
+    fn test_new() {
+        let tensor_context = create_tensor_context!(10);
+        let embedding = Embedding::new(tensor_context.clone(), 5, 3, Some(1));
+        assert_eq!((embedding.vocab(), embedding.dim()), (5, 3));
+        assert_eq!(embedding.vector(1), vec![0.0; 3]);
+        assert!(embedding.vector(0).iter().all(|a| a.abs() < 0.05));
+        assert_eq!(embedding.get_parameters().len(), 1);
+    }
+
+    #[test]
+    fn test_forward() {
+        let tensor_context = create_tensor_context!(10);
+        let mut embedding = pretrained(&tensor_context, None);
+        // A batch of two sequences of two tokens
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![2, 2], vec![2.0, 1.0, 0.0, 2.0]);
+        embedding.compile(input);
+
+        tensor_context.borrow_mut().set_data(input, vec![1.0, 1.0, 2.0, 0.0]);
+        let output = embedding.forward(input);
+        let output = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output.shape, vec![2, 2, 2]);
+        assert_eq!(output.data, vec![1.0, 2.0, 1.0, 2.0, 3.0, 4.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_backwards_only_reaches_looked_up_rows() {
+        let tensor_context = create_tensor_context!(10);
+        let mut embedding = pretrained(&tensor_context, Some(0));
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![4], vec![2.0, 0.0, 2.0, 0.0]);
+        let output = embedding.compile(input);
+
+        let loss = tensor_context.borrow_mut().sum(output);
+        tensor_context.borrow_mut().backwards(loss);
+        let table = embedding.get_parameters()[0];
+        // Token 2 is looked up twice, token 1 never and token 0 is padding
+        assert_eq!(
+            tensor_context.borrow().get_tensor(table).grad,
+            Some(vec![0.0, 0.0, 0.0, 0.0, 2.0, 2.0])
+        );
+    }
+
+    #[test]
+    fn test_frozen_pretrained_vectors() {
+        let tensor_context = create_tensor_context!(10);
+        let vectors = Tensor::new(vec![2, 2], vec![1.0, 2.0, 3.0, 4.0]);
+        let embedding = Embedding::from_pretrained(tensor_context, vectors, None, false);
+        assert!(embedding.get_parameters().is_empty());
+        assert_eq!(embedding.vector(1), vec![3.0, 4.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pretrained vectors must have shape [vocab, dim]")]
+    fn test_pretrained_vectors_not_a_table() {
+        let tensor_context = create_tensor_context!(10);
+        Embedding::from_pretrained(tensor_context, Tensor::new(vec![4], vec![0.0; 4]), None, true);
+    }
+
+    #[test]
+    #[should_panic(expected = "Padding index 3 is outside a vocabulary of 3")]
+    fn test_padding_index_out_of_range() {
+        let tensor_context = create_tensor_context!(10);
+        Embedding::new(tensor_context, 3, 2, Some(3));
+    }
+}
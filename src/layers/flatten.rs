@@ -13,13 +13,24 @@ pub struct Flatten {
 
 impl Layer for Flatten {
     fn forward(&self, input: TensorRef) -> TensorRef { 
-        self.tensor_context.borrow_mut().concat_inplace(vec![input], self.output_tensor.unwrap());
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        let shape = self.flattened_shape(&tensor_context.get_shape(input));
+        tensor_context.concat_inplace(vec![input], self.output_tensor.unwrap());
+        tensor_context.set_shape(self.output_tensor.unwrap(), shape);
         self.output_tensor.unwrap()
     }
 
     fn compile(&mut self, input: TensorRef) -> TensorRef {
-        self.output_tensor = Some(self.tensor_context.borrow_mut().concat(vec![input]));
-        self.output_tensor.unwrap() 
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        let shape = tensor_context.get_shape(input);
+        let output = if shape == self.input_shape {
+            tensor_context.reshape(input, self.flattened_shape(&shape))
+        } else {
+            // The batch axis follows the input when the graph is recomputed for another batch size
+            tensor_context.reshape_samples(input, vec![shape[1..].iter().product()])
+        };
+        self.output_tensor = Some(output);
+        output
     }
 
     fn get_parameters(&self) -> Vec<TensorRef> {
@@ -35,6 +46,15 @@ impl Flatten {
             output_tensor: None,
         }
     }
+
+    // Flattens a single sample to one axis, or each sample of a batch to one axis apiece
+    fn flattened_shape(&self, shape: &[usize]) -> Vec<usize> {
+        if shape == self.input_shape.as_slice() {
+            vec![shape.iter().product()]
+        } else {
+            vec![shape[0], shape[1..].iter().product()]
+        }
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -73,4 +93,18 @@ mod tests {
 
         assert_eq!(output1, output2);
     }
+
+    #[test]
+    fn test_flatten_batch() {
+        let tensor_context = create_tensor_context!(1024);
+        let input = tensor_context.borrow_mut().new_tensor(vec![1, 2, 3], vec![1.0; 6]);
+        let mut flatten = Flatten::new(tensor_context.clone(), vec![2, 3]);
+
+        let output = flatten.compile(input);
+        assert_eq!(tensor_context.borrow_mut().get_shape(output), vec![1, 6]);
+
+        let input = tensor_context.borrow_mut().new_tensor(vec![4, 2, 3], vec![1.0; 24]);
+        let output = flatten.forward(input);
+        assert_eq!(tensor_context.borrow_mut().get_shape(output), vec![4, 6]);
+    }
 }
@@ -0,0 +1,84 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::math::{
+    im2col::Window,
+    tensor_context::{TensorContext, TensorRef},
+};
+
+use super::layers::layers::Layer;
+
+// Averages each channel of an NCHW tensor over its whole height and width, giving a tensor of
+// shape [batch, channels]
+pub struct GlobalAveragePool {
+    tensor_context: Rc<RefCell<TensorContext>>,
+    pooled_tensor: Option<TensorRef>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for GlobalAveragePool {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        tensor_context.recompute(self.pooled_tensor.unwrap());
+        tensor_context.recompute(self.output_tensor.unwrap());
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        let shape = tensor_context.get_shape(input);
+        if shape.len() != 4 {
+            panic!("Expected an NCHW tensor, got shape {:?}", shape);
+        }
+
+        let pooled = tensor_context.avg_pool(input, Window::new((shape[2], shape[3])));
+        // The leading axis follows the batch size of the input
+        let output = tensor_context.reshape_samples(pooled, vec![shape[1]]);
+        self.pooled_tensor = Some(pooled);
+        self.output_tensor = Some(output);
+        output
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        vec![]
+    }
+}
+
+impl GlobalAveragePool {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>) -> GlobalAveragePool {
+        GlobalAveragePool {
+            tensor_context,
+            pooled_tensor: None,
+            output_tensor: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::create_tensor_context;
+
+    use super::*;
+
+    #[test]
+    fn test_forward_and_backward() {
+        let tensor_context = create_tensor_context!(10);
+        let mut pool = GlobalAveragePool::new(tensor_context.clone());
+        let data: Vec<f64> = (1..=8).map(|a| a as f64).collect();
+        let input = tensor_context.borrow_mut().new_tensor(vec![1, 2, 2, 2], data.clone());
+        pool.compile(input);
+
+        // A batch of two with the second sample doubled
+        let mut batch = data.clone();
+        batch.extend(data.iter().map(|a| a * 2.0));
+        tensor_context.borrow_mut().set_shape(input, vec![2, 2, 2, 2]);
+        tensor_context.borrow_mut().set_data(input, batch);
+        let output = pool.forward(input);
+        let output_tensor = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output_tensor.shape, vec![2, 2]);
+        assert_eq!(output_tensor.data, vec![2.5, 6.5, 5.0, 13.0]);
+
+        let loss = tensor_context.borrow_mut().sum(output);
+        tensor_context.borrow_mut().backwards(loss);
+        assert_eq!(tensor_context.borrow().get_tensor(input).grad, Some(vec![0.25; 16]));
+    }
+}
@@ -17,13 +17,19 @@ impl Layer for Input {
     }
 
     fn compile(&mut self, input: TensorRef) -> TensorRef {
-        let input_size = self.tensor_context.borrow().get_tensor(input);
-
-        if input_size.shape != self.size {
+        let input_shape = self.tensor_context.borrow().get_shape(input);
+
+        // A batched input carries the samples along an extra leading axis
+        let sample_shape = if input_shape.len() == self.size.len() + 1 {
+            &input_shape[1..]
+        } else {
+            &input_shape[..]
+        };
+        if sample_shape != self.size.as_slice() {
             panic!("Input size does not match the expected size of the input layer");
         }
 
-        let new_fixed_tensor = self.tensor_context.borrow_mut().new_tensor(self.size.clone(), vec![0.0; self.size.iter().product::<usize>()]);
+        let new_fixed_tensor = self.tensor_context.borrow_mut().new_tensor(input_shape.clone(), vec![0.0; input_shape.iter().product::<usize>()]);
         self.fixed_input_tensor = Some(new_fixed_tensor);
         new_fixed_tensor
     }
@@ -0,0 +1,129 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::math::tensor_context::{TensorContext, TensorRef};
+
+use super::layers::layers::Layer;
+
+// Normalizes every row along the last axis to zero mean and unit variance, then scales by gamma
+// and shifts by beta. Any leading batch or sequence axes are left alone.
+pub struct LayerNorm {
+    pub epsilon: f64,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    gamma: Option<TensorRef>,
+    beta: Option<TensorRef>,
+    // Tensors recorded by compile, in the order they need recomputing on a forward pass
+    forward_tensors: Vec<TensorRef>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for LayerNorm {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        for tensor in self.forward_tensors.iter() {
+            tensor_context.recompute(*tensor);
+        }
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        let size = *self.tensor_context.borrow().get_shape(input).last().unwrap();
+        let needs_parameters = match self.gamma {
+            Some(gamma) => self.tensor_context.borrow().get_shape(gamma)[0] != size,
+            None => true,
+        };
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        if needs_parameters {
+            self.gamma = Some(tensor_context.new_tensor(vec![size], vec![1.0; size]));
+            self.beta = Some(tensor_context.new_tensor(vec![size], vec![0.0; size]));
+        }
+
+        let normalized = tensor_context.layer_normalize(input, self.epsilon);
+        let scaled = tensor_context.mul(normalized, self.gamma.unwrap());
+        let output = tensor_context.add(scaled, self.beta.unwrap());
+        self.forward_tensors = vec![normalized, scaled, output];
+        self.output_tensor = Some(output);
+        output
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        vec![self.gamma.unwrap(), self.beta.unwrap()]
+    }
+}
+
+impl LayerNorm {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, epsilon: f64) -> LayerNorm {
+        LayerNorm {
+            epsilon,
+            tensor_context,
+            gamma: None,
+            beta: None,
+            forward_tensors: vec![],
+            output_tensor: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_tensor_context, math::gradcheck::gradcheck};
+
+    use super::*;
+
+    #[test]
+    fn test_forward() {
+        let tensor_context = create_tensor_context!(100);
+        let mut layer_norm = LayerNorm::new(tensor_context.clone(), 0.0);
+        // A batch of one sequence of two tokens
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![1, 2, 2], vec![1.0, 3.0, 10.0, 0.0]);
+        let output = layer_norm.compile(input);
+
+        let parameters = layer_norm.get_parameters();
+        tensor_context.borrow_mut().set_data(parameters[0], vec![2.0, 1.0]);
+        tensor_context.borrow_mut().set_data(parameters[1], vec![0.5, 0.0]);
+        layer_norm.forward(input);
+
+        let output = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output.shape, vec![1, 2, 2]);
+        assert_eq!(output.data, vec![-1.5, 1.0, 2.5, -1.0]);
+    }
+
+    #[test]
+    fn test_backwards_reaches_parameters() {
+        let tensor_context = create_tensor_context!(100);
+        let mut layer_norm = LayerNorm::new(tensor_context.clone(), 1e-5);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![3, 2], vec![1.0, 2.0, 4.0, 3.0, 0.0, 5.0]);
+        let output = layer_norm.compile(input);
+        layer_norm.forward(input);
+
+        let loss = tensor_context.borrow_mut().sum(output);
+        tensor_context.borrow_mut().backwards(loss);
+        let parameters = layer_norm.get_parameters();
+        // Every row normalizes to roughly [-1, 1] or [1, -1]
+        let gamma_grad = tensor_context.borrow().get_tensor(parameters[0]).grad.unwrap();
+        assert!((gamma_grad[0] + gamma_grad[1]).abs() < 1e-9);
+        assert_eq!(tensor_context.borrow().get_tensor(parameters[1]).grad, Some(vec![3.0, 3.0]));
+    }
+
+    #[test]
+    fn test_gradcheck_layer_norm() {
+        let data: Vec<f64> = (0..2 * 2 * 3).map(|a| (a as f64 * 0.8).sin()).collect();
+        let report = gradcheck(
+            vec![(vec![2, 2, 3], data), (vec![3], vec![0.5, -1.0, 2.0]), (vec![3], vec![0.1, 0.0, -0.2])],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                let normalized = tensor_context.layer_normalize(inputs[0], 1e-6);
+                let scaled = tensor_context.mul(normalized, inputs[1]);
+                let shifted = tensor_context.add(scaled, inputs[2]);
+                let squared = tensor_context.mul(shifted, shifted);
+                let cubed = tensor_context.mul(squared, shifted);
+                tensor_context.sum(cubed)
+            },
+        );
+        assert!(report.passed(1e-5), "{:?}", report.failures(1e-5));
+    }
+}
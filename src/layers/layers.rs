@@ -8,5 +8,7 @@ pub mod layers {
         fn forward(&self, input: TensorRef) -> TensorRef;
         fn compile(&mut self, input: TensorRef) -> TensorRef;
         fn get_parameters(&self) -> Vec<TensorRef>;
+        // Switches between training and inference behaviour, for layers that have both
+        fn set_training(&mut self, _training: bool) {}
     } 
 }
\ No newline at end of file
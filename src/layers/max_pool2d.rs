@@ -0,0 +1,77 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::math::{
+    im2col::Window,
+    tensor_context::{TensorContext, TensorRef},
+};
+
+use super::layers::layers::Layer;
+
+// Keeps the largest value under every position of a window over each channel of an NCHW tensor
+pub struct MaxPool2D {
+    window: Window,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for MaxPool2D {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        self.tensor_context.borrow_mut().recompute(self.output_tensor.unwrap());
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        self.output_tensor = Some(self.tensor_context.borrow_mut().max_pool(input, self.window));
+        self.output_tensor.unwrap()
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        vec![]
+    }
+}
+
+impl MaxPool2D {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, window: Window) -> MaxPool2D {
+        MaxPool2D {
+            window,
+            tensor_context,
+            output_tensor: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::create_tensor_context;
+
+    use super::*;
+
+    #[test]
+    fn test_forward_and_backward() {
+        let tensor_context = create_tensor_context!(10);
+        let window = Window {
+            stride: (2, 2),
+            ..Window::new((2, 2))
+        };
+        let mut pool = MaxPool2D::new(tensor_context.clone(), window);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![1, 2, 2, 2], vec![1.0, 4.0, 3.0, 2.0, -1.0, -2.0, -3.0, -4.0]);
+        pool.compile(input);
+
+        tensor_context
+            .borrow_mut()
+            .set_data(input, vec![5.0, 4.0, 3.0, 2.0, -4.0, -2.0, -3.0, -1.0]);
+        let output = pool.forward(input);
+        let output_tensor = tensor_context.borrow().get_tensor(output);
+        assert_eq!(output_tensor.shape, vec![1, 2, 1, 1]);
+        assert_eq!(output_tensor.data, vec![5.0, -1.0]);
+
+        let loss = tensor_context.borrow_mut().sum(output);
+        tensor_context.borrow_mut().backwards(loss);
+        assert_eq!(
+            tensor_context.borrow().get_tensor(input).grad,
+            Some(vec![1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0])
+        );
+    }
+}
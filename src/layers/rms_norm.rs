@@ -0,0 +1,105 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::math::tensor_context::{TensorContext, TensorRef};
+
+use super::layers::layers::Layer;
+
+// Divides every row along the last axis by its root mean square, then scales by gamma. Cheaper
+// than LayerNorm as it neither centers the rows nor shifts them afterwards.
+pub struct RMSNorm {
+    pub epsilon: f64,
+    tensor_context: Rc<RefCell<TensorContext>>,
+    gamma: Option<TensorRef>,
+    // Tensors recorded by compile, in the order they need recomputing on a forward pass
+    forward_tensors: Vec<TensorRef>,
+    output_tensor: Option<TensorRef>,
+}
+
+impl Layer for RMSNorm {
+    fn forward(&self, _input: TensorRef) -> TensorRef {
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        for tensor in self.forward_tensors.iter() {
+            tensor_context.recompute(*tensor);
+        }
+        self.output_tensor.unwrap()
+    }
+
+    fn compile(&mut self, input: TensorRef) -> TensorRef {
+        let size = *self.tensor_context.borrow().get_shape(input).last().unwrap();
+        let needs_parameters = match self.gamma {
+            Some(gamma) => self.tensor_context.borrow().get_shape(gamma)[0] != size,
+            None => true,
+        };
+
+        let mut tensor_context = self.tensor_context.borrow_mut();
+        if needs_parameters {
+            self.gamma = Some(tensor_context.new_tensor(vec![size], vec![1.0; size]));
+        }
+
+        let normalized = tensor_context.rms_normalize(input, self.epsilon);
+        let output = tensor_context.mul(normalized, self.gamma.unwrap());
+        self.forward_tensors = vec![normalized, output];
+        self.output_tensor = Some(output);
+        output
+    }
+
+    fn get_parameters(&self) -> Vec<TensorRef> {
+        vec![self.gamma.unwrap()]
+    }
+}
+
+impl RMSNorm {
+    pub fn new(tensor_context: Rc<RefCell<TensorContext>>, epsilon: f64) -> RMSNorm {
+        RMSNorm {
+            epsilon,
+            tensor_context,
+            gamma: None,
+            forward_tensors: vec![],
+            output_tensor: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{create_tensor_context, math::gradcheck::gradcheck};
+
+    use super::*;
+
+    #[test]
+    fn test_forward() {
+        let tensor_context = create_tensor_context!(100);
+        let mut rms_norm = RMSNorm::new(tensor_context.clone(), 0.0);
+        let input = tensor_context
+            .borrow_mut()
+            .new_tensor(vec![2, 2], vec![3.0, 4.0, -1.0, 1.0]);
+        let output = rms_norm.compile(input);
+        tensor_context
+            .borrow_mut()
+            .set_data(rms_norm.get_parameters()[0], vec![1.0, 2.0]);
+        rms_norm.forward(input);
+
+        let expected = [3.0 / 12.5_f64.sqrt(), 8.0 / 12.5_f64.sqrt(), -1.0, 2.0];
+        let output = tensor_context.borrow().get_tensor(output).data;
+        for (actual, expected) in output.iter().zip(expected.iter()) {
+            assert!((actual - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_gradcheck_rms_norm() {
+        let data: Vec<f64> = (0..2 * 2 * 3).map(|a| (a as f64 * 0.8).sin()).collect();
+        let report = gradcheck(
+            vec![(vec![2, 2, 3], data), (vec![3], vec![0.5, -1.0, 2.0])],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                let normalized = tensor_context.rms_normalize(inputs[0], 1e-6);
+                let scaled = tensor_context.mul(normalized, inputs[1]);
+                let squared = tensor_context.mul(scaled, scaled);
+                let cubed = tensor_context.mul(squared, scaled);
+                tensor_context.sum(cubed)
+            },
+        );
+        assert!(report.passed(1e-5), "{:?}", report.failures(1e-5));
+    }
+}
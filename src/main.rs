@@ -1,10 +1,14 @@
 
-use file::idx_reader;
+use file::{
+    idx_reader,
+    svg_plot::{self, PlotOptions},
+};
 use graph::{
-    graph::{Model, Sequential},
+    callbacks::EarlyStopping,
+    graph::{Model, Sequential, Validation},
     loss_function::LossFunction,
     network_metric::Metric,
-    optimizer::Optimizer,
+    optimizer::SGD,
 };
 use layers::{dense::Dense, dropout::Dropout, flatten::Flatten, input::Input, layers::layers::Layer};
 use nuerons::activation_function::ActivationFunction;
@@ -47,14 +51,23 @@ fn try_sin() {
     network.compile(
         vec![1],
         vec![1],
-        Optimizer::SGD,
+        Box::new(SGD::new(0.1)),
         LossFunction::MeanSquaredError,
-        vec![Metric::Accuracy],
+        vec![Metric::MeanAbsoluteError, Metric::RSquared],
     );
 
     let epochs = 100;
+    let mut early_stopping = EarlyStopping::new("val_loss", 10, true);
     
-    network.fit(training_data.clone(), training_labels.clone(), epochs);
+    let history = network.fit(
+        training_data.clone(),
+        training_labels.clone(),
+        epochs,
+        32,
+        Validation::Split { fraction: 0.1, shuffle: true },
+        &mut [&mut early_stopping],
+    );
+    svg_plot::save_history_plot(&history, "loss.svg", &PlotOptions::default()).unwrap();
 
     println!("Training done!");
 
@@ -76,7 +89,7 @@ fn try_MNIST() {
     let layers: Vec<Box<dyn Layer>> = vec![
         Box::new(Flatten::new(tensor_context.clone(), vec![28, 28])),
         Box::new(Dense::new(tensor_context.clone(), 10, ActivationFunction::ReLU)),
-        Box::new(Dropout::new(tensor_context.clone(), 0.2, false)),
+        Box::new(Dropout::new(tensor_context.clone(), 0.2)),
         Box::new(Dense::new(tensor_context.clone(), 10, ActivationFunction::ReLU)),
     ];
     let mut network = Sequential::new(tensor_context.clone(), layers);
@@ -87,14 +100,21 @@ fn try_MNIST() {
     network.compile(
         vec![28, 28],
         vec![10],
-        Optimizer::SGD,
-        LossFunction::MeanSquaredError,
+        Box::new(SGD::new(0.1)),
+        LossFunction::SparseCrossEntropy { from_logits: true },
         vec![Metric::Accuracy],
     );
 
 
     let epochs = 10;
-    network.fit(training_data.clone(), training_labels.clone(), epochs);
+    network.fit(
+        training_data.clone(),
+        training_labels.clone(),
+        epochs,
+        32,
+        Validation::Split { fraction: 0.1, shuffle: true },
+        &mut [],
+    );
 
     println!("Training done!");
 
@@ -4,4 +4,15 @@ pub mod math;
 pub mod matrix;
 pub mod tensor;
 pub mod tensor_context;
-pub mod composite_operations;
\ No newline at end of file
+pub mod composite_operations;
+pub mod gradcheck;
+pub mod broadcast;
+pub mod matmul;
+pub mod cross_entropy;
+pub mod softmax;
+pub mod activations;
+pub mod custom_op;
+pub mod im2col;
+pub mod pooling;
+pub mod normalization;
+pub mod embedding;
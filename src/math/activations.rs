@@ -0,0 +1,95 @@
+// Scalar forms of the smooth activation functions and their derivatives with respect to the input
+
+// Constants from the SELU paper that make activations self-normalising
+pub const SELU_ALPHA: f64 = 1.6732632423543772;
+pub const SELU_SCALE: f64 = 1.0507009873554805;
+
+// sqrt(2 / π), used by the tanh approximation of GELU
+const GELU_COEFFICIENT: f64 = 0.7978845608028654;
+const GELU_CUBIC: f64 = 0.044715;
+
+pub fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+// ln(1 + eˣ), rearranged so large inputs neither overflow nor lose precision
+pub fn softplus(x: f64) -> f64 {
+    x.max(0.0) + (-x.abs()).exp().ln_1p()
+}
+
+pub fn softplus_derivative(x: f64) -> f64 {
+    sigmoid(x)
+}
+
+// Tanh approximation of x·Φ(x)
+pub fn gelu(x: f64) -> f64 {
+    0.5 * x * (1.0 + (GELU_COEFFICIENT * (x + GELU_CUBIC * x.powi(3))).tanh())
+}
+
+pub fn gelu_derivative(x: f64) -> f64 {
+    let tanh = (GELU_COEFFICIENT * (x + GELU_CUBIC * x.powi(3))).tanh();
+    let inner_derivative = GELU_COEFFICIENT * (1.0 + 3.0 * GELU_CUBIC * x * x);
+    0.5 * (1.0 + tanh) + 0.5 * x * (1.0 - tanh * tanh) * inner_derivative
+}
+
+// Also known as swish: x·σ(x)
+pub fn silu(x: f64) -> f64 {
+    x * sigmoid(x)
+}
+
+pub fn silu_derivative(x: f64) -> f64 {
+    let sigmoid = sigmoid(x);
+    sigmoid * (1.0 + x * (1.0 - sigmoid))
+}
+
+pub fn elu(x: f64, alpha: f64) -> f64 {
+    if x > 0.0 {
+        x
+    } else {
+        alpha * x.exp_m1()
+    }
+}
+
+pub fn elu_derivative(x: f64, alpha: f64) -> f64 {
+    if x > 0.0 {
+        1.0
+    } else {
+        alpha * x.exp()
+    }
+}
+
+pub fn selu(x: f64) -> f64 {
+    SELU_SCALE * elu(x, SELU_ALPHA)
+}
+
+pub fn selu_derivative(x: f64) -> f64 {
+    SELU_SCALE * elu_derivative(x, SELU_ALPHA)
+}
+
+// x·tanh(softplus(x))
+pub fn mish(x: f64) -> f64 {
+    x * softplus(x).tanh()
+}
+
+pub fn mish_derivative(x: f64) -> f64 {
+    let tanh = softplus(x).tanh();
+    tanh + x * (1.0 - tanh * tanh) * sigmoid(x)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_softplus_large_inputs() {
+        assert_eq!(softplus(1000.0), 1000.0);
+        assert_eq!(softplus(-1000.0), 0.0);
+        assert!((softplus(0.0) - 2.0_f64.ln()).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_selu_is_continuous_at_zero() {
+        assert_eq!(selu(0.0), 0.0);
+        assert!((selu(1.0) - SELU_SCALE).abs() < 1e-15);
+    }
+}
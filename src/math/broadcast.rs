@@ -0,0 +1,118 @@
+// NumPy style broadcasting: shapes are aligned on their trailing axes and an axis of size 1 (or a
+// missing leading axis) is repeated to match the other shape.
+
+pub fn broadcast_shapes(left: &[usize], right: &[usize]) -> Option<Vec<usize>> {
+    let rank = left.len().max(right.len());
+    (0..rank)
+        .map(|axis| {
+            match (axis_size(left, rank, axis), axis_size(right, rank, axis)) {
+                (a, b) if a == b => Some(a),
+                (1, b) => Some(b),
+                (a, 1) => Some(a),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Like broadcast_shapes but panics with both shapes in the message when they are incompatible
+pub fn broadcast_shapes_or_panic(left: &[usize], right: &[usize]) -> Vec<usize> {
+    broadcast_shapes(left, right).unwrap_or_else(|| {
+        panic!("Cannot broadcast tensors of shape {:?} and {:?}", left, right)
+    })
+}
+
+// Repeats data of the given shape so that it fills output_shape
+pub fn expand(data: &[f64], shape: &[usize], output_shape: &[usize]) -> Vec<f64> {
+    if shape == output_shape {
+        return data.to_vec();
+    }
+    source_indices(shape, output_shape)
+        .into_iter()
+        .map(|index| data[index])
+        .collect()
+}
+
+// Sums a gradient of output_shape back over the broadcast axes so it matches shape
+pub fn reduce_to_shape(grad: &[f64], shape: &[usize], output_shape: &[usize]) -> Vec<f64> {
+    if shape == output_shape {
+        return grad.to_vec();
+    }
+    let mut reduced = vec![0.0; shape.iter().product()];
+    source_indices(shape, output_shape)
+        .into_iter()
+        .zip(grad.iter())
+        .for_each(|(index, grad)| reduced[index] += grad);
+    reduced
+}
+
+// For every element of output_shape, the index of the element of shape it was broadcast from
+fn source_indices(shape: &[usize], output_shape: &[usize]) -> Vec<usize> {
+    let rank = output_shape.len();
+    let sizes: Vec<usize> = (0..rank).map(|axis| axis_size(shape, rank, axis)).collect();
+
+    // Broadcast axes get a stride of zero so moving along them never changes the source index
+    let mut strides = vec![0; rank];
+    let mut stride = 1;
+    for axis in (0..rank).rev() {
+        if sizes[axis] != 1 {
+            strides[axis] = stride;
+        }
+        stride *= sizes[axis];
+    }
+
+    let mut indices = Vec::with_capacity(output_shape.iter().product());
+    let mut position = vec![0; rank];
+    for _ in 0..output_shape.iter().product::<usize>() {
+        indices.push(position.iter().zip(strides.iter()).map(|(p, s)| p * s).sum());
+        for axis in (0..rank).rev() {
+            position[axis] += 1;
+            if position[axis] < output_shape[axis] {
+                break;
+            }
+            position[axis] = 0;
+        }
+    }
+    indices
+}
+
+// Size of an axis once the shape is left padded with ones up to rank
+fn axis_size(shape: &[usize], rank: usize, axis: usize) -> usize {
+    let padding = rank - shape.len();
+    if axis < padding {
+        1
+    } else {
+        shape[axis - padding]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast_shapes() {
+        assert_eq!(broadcast_shapes(&[4, 3], &[3]), Some(vec![4, 3]));
+        assert_eq!(broadcast_shapes(&[4, 1], &[1, 3]), Some(vec![4, 3]));
+        assert_eq!(broadcast_shapes(&[2, 1, 5], &[3, 1]), Some(vec![2, 3, 5]));
+        assert_eq!(broadcast_shapes(&[1], &[]), Some(vec![1]));
+        assert_eq!(broadcast_shapes(&[4, 3], &[2]), None);
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(
+            expand(&[1.0, 2.0, 3.0], &[3], &[2, 3]),
+            vec![1.0, 2.0, 3.0, 1.0, 2.0, 3.0]
+        );
+        assert_eq!(expand(&[1.0, 2.0], &[2, 1], &[2, 2]), vec![1.0, 1.0, 2.0, 2.0]);
+    }
+
+    #[test]
+    fn test_reduce_to_shape() {
+        let grad = vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        assert_eq!(reduce_to_shape(&grad, &[3], &[2, 3]), vec![5.0, 7.0, 9.0]);
+        assert_eq!(reduce_to_shape(&grad, &[2, 1], &[2, 3]), vec![6.0, 15.0]);
+        assert_eq!(reduce_to_shape(&grad, &[1], &[2, 3]), vec![21.0]);
+    }
+}
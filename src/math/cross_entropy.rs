@@ -0,0 +1,121 @@
+// Cross entropy between predictions and target distributions laid out as rows along the last
+// axis, averaged over the rows. Predictions are either unnormalised logits or probabilities.
+
+// Probabilities are clamped to this before taking their log so a confident mistake stays finite
+pub const PROBABILITY_EPSILON: f64 = 1e-12;
+
+// Log probabilities of every row. Logits go through a log-softmax that subtracts the row maximum
+// before exponentiating, so large logits cannot overflow.
+pub fn log_probabilities(data: &[f64], classes: usize, from_logits: bool) -> Vec<f64> {
+    if !from_logits {
+        return data.iter().map(|p| p.max(PROBABILITY_EPSILON).ln()).collect();
+    }
+
+    let mut log_probabilities = Vec::with_capacity(data.len());
+    for row in data.chunks(classes) {
+        let max = row.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let log_sum_exp = max + row.iter().map(|a| (a - max).exp()).sum::<f64>().ln();
+        log_probabilities.extend(row.iter().map(|a| a - log_sum_exp));
+    }
+    log_probabilities
+}
+
+// Expands one class index per row into one-hot target rows
+pub fn one_hot(labels: &[f64], classes: usize) -> Vec<f64> {
+    let mut targets = vec![0.0; labels.len() * classes];
+    for (row, label) in labels.iter().enumerate() {
+        if label.fract() != 0.0 || *label < 0.0 || *label >= classes as f64 {
+            panic!("Label {} is not a class index below {}", label, classes);
+        }
+        targets[row * classes + *label as usize] = 1.0;
+    }
+    targets
+}
+
+pub fn forward(log_probabilities: &[f64], targets: &[f64], classes: usize) -> f64 {
+    let rows = (log_probabilities.len() / classes).max(1);
+    -log_probabilities
+        .iter()
+        .zip(targets.iter())
+        .map(|(log_probability, target)| log_probability * target)
+        .sum::<f64>()
+        / rows as f64
+}
+
+// Gradients of the loss with respect to the predictions and the targets, given the gradient of
+// the loss itself
+pub fn backward(
+    predictions: &[f64],
+    targets: &[f64],
+    classes: usize,
+    from_logits: bool,
+    grad: f64,
+) -> (Vec<f64>, Vec<f64>) {
+    let rows = (predictions.len() / classes).max(1);
+    let scale = grad / rows as f64;
+    let log_probabilities = log_probabilities(predictions, classes, from_logits);
+
+    let prediction_grad = if from_logits {
+        // d/dz of -Σ t·log_softmax(z) is softmax(z)·Σt - t
+        let mut prediction_grad = Vec::with_capacity(predictions.len());
+        for (row, target_row) in log_probabilities.chunks(classes).zip(targets.chunks(classes)) {
+            let target_sum = target_row.iter().sum::<f64>();
+            prediction_grad.extend(
+                row.iter()
+                    .zip(target_row.iter())
+                    .map(|(log_probability, target)| {
+                        (log_probability.exp() * target_sum - target) * scale
+                    }),
+            );
+        }
+        prediction_grad
+    } else {
+        predictions
+            .iter()
+            .zip(targets.iter())
+            .map(|(p, target)| {
+                if *p > PROBABILITY_EPSILON {
+                    -target / p * scale
+                } else {
+                    0.0
+                }
+            })
+            .collect()
+    };
+    let target_grad = log_probabilities.iter().map(|a| -a * scale).collect();
+
+    (prediction_grad, target_grad)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_probabilities_from_large_logits() {
+        let log_probabilities = log_probabilities(&[1000.0, 1000.0, 0.0, 0.0], 2, true);
+        let expected = -(2.0_f64.ln());
+        log_probabilities
+            .iter()
+            .for_each(|a| assert!((a - expected).abs() < 1e-12, "{:?}", log_probabilities));
+    }
+
+    #[test]
+    fn test_forward_matches_probabilities() {
+        let logits = [1.0, 2.0, 3.0];
+        let sum = logits.iter().map(|a: &f64| a.exp()).sum::<f64>();
+        let probabilities: Vec<f64> = logits.iter().map(|a| a.exp() / sum).collect();
+        let targets = one_hot(&[2.0], 3);
+
+        let from_logits = forward(&log_probabilities(&logits, 3, true), &targets, 3);
+        let from_probabilities = forward(&log_probabilities(&probabilities, 3, false), &targets, 3);
+        assert!((from_logits - from_probabilities).abs() < 1e-12);
+        assert!((from_logits + probabilities[2].ln()).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a class index")]
+    fn test_one_hot_out_of_range() {
+        one_hot(&[3.0], 3);
+    }
+}
@@ -0,0 +1,122 @@
+use std::fmt::Debug;
+
+// Index of an operation registered with a TensorContext
+pub type CustomOpRef = usize;
+
+// Read-only view of one tensor handed to a custom operation
+#[derive(Debug, Clone, Copy)]
+pub struct OpInput<'a> {
+    pub shape: &'a [usize],
+    pub data: &'a [f64],
+}
+
+// An operation defined outside the crate. Once registered with TensorContext::register_custom_op
+// it can be recorded with TensorContext::custom, or used as ActivationFunction::Custom when it
+// takes a single input.
+pub trait CustomOp: Debug {
+    // Used in error messages
+    fn name(&self) -> &str;
+
+    // Shape and data of the output
+    fn forward(&self, inputs: &[OpInput]) -> (Vec<usize>, Vec<f64>);
+
+    // Gradient of every input, in the same order and with the same length as the inputs, given
+    // the gradient of the output
+    fn backward(&self, inputs: &[OpInput], output: OpInput, output_grad: &[f64]) -> Vec<Vec<f64>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{math::gradcheck::gradcheck, nuerons::activation_function::ActivationFunction};
+
+    use super::*;
+
+    // x³ elementwise
+    #[derive(Debug)]
+    struct Cube;
+
+    impl CustomOp for Cube {
+        fn name(&self) -> &str {
+            "cube"
+        }
+
+        fn forward(&self, inputs: &[OpInput]) -> (Vec<usize>, Vec<f64>) {
+            (inputs[0].shape.to_vec(), inputs[0].data.iter().map(|a| a.powi(3)).collect())
+        }
+
+        fn backward(&self, inputs: &[OpInput], _output: OpInput, output_grad: &[f64]) -> Vec<Vec<f64>> {
+            let grad = inputs[0]
+                .data
+                .iter()
+                .zip(output_grad.iter())
+                .map(|(a, grad)| 3.0 * a * a * grad)
+                .collect();
+            vec![grad]
+        }
+    }
+
+    // Elementwise a·b + a
+    #[derive(Debug)]
+    struct MulAdd;
+
+    impl CustomOp for MulAdd {
+        fn name(&self) -> &str {
+            "mul_add"
+        }
+
+        fn forward(&self, inputs: &[OpInput]) -> (Vec<usize>, Vec<f64>) {
+            let data = inputs[0]
+                .data
+                .iter()
+                .zip(inputs[1].data.iter())
+                .map(|(a, b)| a * b + a)
+                .collect();
+            (inputs[0].shape.to_vec(), data)
+        }
+
+        fn backward(&self, inputs: &[OpInput], _output: OpInput, output_grad: &[f64]) -> Vec<Vec<f64>> {
+            let (left, right) = (inputs[0].data, inputs[1].data);
+            let left_grad = right.iter().zip(output_grad).map(|(b, grad)| (b + 1.0) * grad).collect();
+            let right_grad = left.iter().zip(output_grad).map(|(a, grad)| a * grad).collect();
+            vec![left_grad, right_grad]
+        }
+    }
+
+    #[test]
+    fn test_custom_activation() {
+        let tensor_context = create_tensor_context!(10);
+        let mut tensor_context = tensor_context.borrow_mut();
+        let cube = tensor_context.register_custom_op(Cube);
+        let input = tensor_context.new_tensor(vec![2], vec![2.0, -1.0]);
+        let output = tensor_context.apply(ActivationFunction::Custom(cube), input);
+        assert_eq!(tensor_context.get_tensor(output).data, vec![8.0, -1.0]);
+
+        tensor_context.set_data(input, vec![3.0, 0.5]);
+        tensor_context.recompute(output);
+        assert_eq!(tensor_context.get_tensor(output).data, vec![27.0, 0.125]);
+    }
+
+    #[test]
+    fn test_gradcheck_custom_op() {
+        let report = gradcheck(
+            vec![(vec![3], vec![0.5, -1.0, 2.0]), (vec![3], vec![1.5, 0.25, -0.75])],
+            |tensor_context, inputs| {
+                let mul_add = tensor_context.borrow_mut().register_custom_op(MulAdd);
+                let cube = tensor_context.borrow_mut().register_custom_op(Cube);
+                let output = tensor_context.borrow_mut().custom(mul_add, inputs.to_vec());
+                let output = tensor_context.borrow_mut().custom(cube, vec![output]);
+                tensor_context.borrow_mut().sum(output)
+            },
+        );
+        assert!(report.passed(1e-6), "{:?}", report.failures(1e-6));
+    }
+
+    #[test]
+    #[should_panic(expected = "No custom operation registered")]
+    fn test_unregistered_custom_op() {
+        let tensor_context = create_tensor_context!(10);
+        let mut tensor_context = tensor_context.borrow_mut();
+        let input = tensor_context.new_tensor(vec![1], vec![1.0]);
+        tensor_context.custom(0, vec![input]);
+    }
+}
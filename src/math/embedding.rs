@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+// Converts token indices stored as floats into row indices of a table with vocab rows
+pub fn token_indices(indices: &[f64], vocab: usize) -> Vec<usize> {
+    indices
+        .iter()
+        .map(|index| {
+            if index.fract() != 0.0 || *index < 0.0 || *index >= vocab as f64 {
+                panic!("Index {} is not a token index below {}", index, vocab);
+            }
+            *index as usize
+        })
+        .collect()
+}
+
+// Shape of the looked up rows: one row of the table for every index
+pub fn output_shape(table_shape: &[usize], indices_shape: &[usize]) -> Vec<usize> {
+    if table_shape.len() != 2 {
+        panic!("Expected an embedding table of shape [vocab, dim], got {:?}", table_shape);
+    }
+    let mut shape = indices_shape.to_vec();
+    shape.push(table_shape[1]);
+    shape
+}
+
+pub fn forward(table: &[f64], dim: usize, tokens: &[usize]) -> Vec<f64> {
+    let mut output = Vec::with_capacity(tokens.len() * dim);
+    for token in tokens {
+        output.extend(&table[token * dim..(token + 1) * dim]);
+    }
+    output
+}
+
+// Gradient of some rows of a table, keyed by row
+pub type RowGrads = HashMap<usize, Vec<f64>>;
+
+// Gradient of every row that was looked up, summed over repeated tokens and leaving out the
+// padding row
+pub fn row_grads(dim: usize, tokens: &[usize], grad: &[f64], padding_index: Option<usize>) -> RowGrads {
+    let mut rows = RowGrads::new();
+    for (token, grad) in tokens.iter().zip(grad.chunks(dim)) {
+        if Some(*token) != padding_index {
+            add_row_grad(&mut rows, *token, grad);
+        }
+    }
+    rows
+}
+
+fn add_row_grad(rows: &mut RowGrads, row: usize, grad: &[f64]) {
+    match rows.get_mut(&row) {
+        Some(row_grad) => row_grad.iter_mut().zip(grad).for_each(|(a, b)| *a += b),
+        None => {
+            rows.insert(row, grad.to_vec());
+        }
+    }
+}
+
+// Adds the row gradients of another pass into rows
+pub fn merge_row_grads(rows: &mut RowGrads, other: RowGrads) {
+    for (row, grad) in other {
+        add_row_grad(rows, row, &grad);
+    }
+}
+
+// Adds row gradients into the dense gradient of a table with rows of dim elements
+pub fn add_to_dense(grad: &mut [f64], dim: usize, rows: &RowGrads) {
+    for (row, row_grad) in rows {
+        grad[row * dim..(row + 1) * dim]
+            .iter_mut()
+            .zip(row_grad.iter())
+            .for_each(|(a, b)| *a += b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::gradcheck::gradcheck;
+
+    use super::*;
+
+    #[test]
+    fn test_forward() {
+        let table = vec![0.0, 0.0, 1.0, 2.0, 3.0, 4.0];
+        let tokens = token_indices(&[2.0, 1.0, 2.0], 3);
+        assert_eq!(forward(&table, 2, &tokens), vec![3.0, 4.0, 1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(output_shape(&[3, 2], &[1, 3]), vec![1, 3, 2]);
+    }
+
+    #[test]
+    fn test_row_grads() {
+        let tokens = vec![2, 0, 2, 1];
+        let grad = vec![1.0, 1.0, 5.0, 5.0, 2.0, 3.0, 7.0, 7.0];
+        let rows = row_grads(2, &tokens, &grad, Some(0));
+        assert_eq!(rows, RowGrads::from([(2, vec![3.0, 4.0]), (1, vec![7.0, 7.0])]));
+
+        let mut dense = vec![0.0; 6];
+        add_to_dense(&mut dense, 2, &rows);
+        assert_eq!(dense, vec![0.0, 0.0, 7.0, 7.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_gradcheck_embedding() {
+        let table: Vec<f64> = (0..4 * 3).map(|a| (a as f64 * 0.8).sin()).collect();
+        let weights: Vec<f64> = (0..2 * 3 * 3).map(|a| (a as f64 * 0.5).cos()).collect();
+        let report = gradcheck(
+            vec![(vec![4, 3], table), (vec![2, 3, 3], weights)],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                // Row 3 is looked up twice and row 0 not at all
+                let indices = tensor_context.new_tensor(vec![2, 3], vec![3.0, 1.0, 2.0, 3.0, 2.0, 1.0]);
+                let rows = tensor_context.embedding(inputs[0], indices, None);
+                let weighted = tensor_context.mul(rows, inputs[1]);
+                let lookup = tensor_context.sum(weighted);
+                // A dense gradient on the same table is added to the row gradients
+                let squared = tensor_context.mul(inputs[0], inputs[0]);
+                let decay = tensor_context.sum(squared);
+                tensor_context.add(lookup, decay)
+            },
+        );
+        assert!(report.passed(1e-6), "{:?}", report.failures(1e-6));
+    }
+
+    #[test]
+    #[should_panic(expected = "not a token index")]
+    fn test_token_out_of_range() {
+        token_indices(&[0.0, 3.0], 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "not a token index")]
+    fn test_token_not_integer() {
+        token_indices(&[0.5], 3);
+    }
+}
@@ -0,0 +1,165 @@
+use std::{cell::RefCell, rc::Rc};
+
+use super::tensor_context::{TensorContext, TensorRef};
+
+// Step used for the central finite differences
+pub const EPSILON: f64 = 1e-6;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradCheckResult {
+    pub input: usize,
+    pub index: usize,
+    pub analytic: f64,
+    pub numeric: f64,
+    pub relative_error: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct GradCheckReport {
+    pub results: Vec<GradCheckResult>,
+}
+
+impl GradCheckReport {
+    pub fn max_relative_error(&self) -> f64 {
+        self.results
+            .iter()
+            .map(|result| result.relative_error)
+            .fold(0.0, f64::max)
+    }
+
+    pub fn passed(&self, tolerance: f64) -> bool {
+        self.max_relative_error() <= tolerance
+    }
+
+    // Results whose relative error exceeds the tolerance
+    pub fn failures(&self, tolerance: f64) -> Vec<&GradCheckResult> {
+        self.results
+            .iter()
+            .filter(|result| result.relative_error > tolerance)
+            .collect()
+    }
+}
+
+// Compares the gradients from backwards against central finite differences.
+//
+// `inputs` holds the shape and data of every input tensor and `build` records a graph on a fresh
+// context from those inputs, returning a single element tensor. The graph is rebuilt for every
+// perturbation, so `build` must be deterministic.
+pub fn gradcheck<F>(inputs: Vec<(Vec<usize>, Vec<f64>)>, build: F) -> GradCheckReport
+where
+    F: Fn(&Rc<RefCell<TensorContext>>, &[TensorRef]) -> TensorRef,
+{
+    let analytic = analytic_grads(&inputs, &build);
+
+    let mut results = Vec::new();
+    for (input, (_, data)) in inputs.iter().enumerate() {
+        for index in 0..data.len() {
+            let mut perturbed = inputs.clone();
+            perturbed[input].1[index] = data[index] + EPSILON;
+            let above = evaluate(&perturbed, &build);
+            perturbed[input].1[index] = data[index] - EPSILON;
+            let below = evaluate(&perturbed, &build);
+
+            let numeric = (above - below) / (2.0 * EPSILON);
+            let analytic = analytic[input][index];
+
+            // Small gradients are compared absolutely, so values near zero do not blow up the error
+            let scale = analytic.abs().max(numeric.abs()).max(1.0);
+            results.push(GradCheckResult {
+                input,
+                index,
+                analytic,
+                numeric,
+                relative_error: (analytic - numeric).abs() / scale,
+            });
+        }
+    }
+
+    GradCheckReport { results }
+}
+
+fn build_graph<F>(
+    inputs: &[(Vec<usize>, Vec<f64>)],
+    build: &F,
+) -> (Rc<RefCell<TensorContext>>, Vec<TensorRef>, TensorRef)
+where
+    F: Fn(&Rc<RefCell<TensorContext>>, &[TensorRef]) -> TensorRef,
+{
+    let tensor_context = create_tensor_context!(64);
+    let input_refs: Vec<TensorRef> = inputs
+        .iter()
+        .map(|(shape, data)| {
+            tensor_context
+                .borrow_mut()
+                .new_tensor(shape.clone(), data.clone())
+        })
+        .collect();
+    let output = build(&tensor_context, &input_refs);
+
+    let output_size = tensor_context.borrow().get_tensor(output).data.len();
+    if output_size != 1 {
+        panic!("Gradient check needs a scalar output, got {} elements", output_size);
+    }
+
+    (tensor_context, input_refs, output)
+}
+
+fn evaluate<F>(inputs: &[(Vec<usize>, Vec<f64>)], build: &F) -> f64
+where
+    F: Fn(&Rc<RefCell<TensorContext>>, &[TensorRef]) -> TensorRef,
+{
+    let (tensor_context, _, output) = build_graph(inputs, build);
+    let value = tensor_context.borrow().get_tensor(output).data[0];
+    value
+}
+
+fn analytic_grads<F>(inputs: &[(Vec<usize>, Vec<f64>)], build: &F) -> Vec<Vec<f64>>
+where
+    F: Fn(&Rc<RefCell<TensorContext>>, &[TensorRef]) -> TensorRef,
+{
+    let (tensor_context, input_refs, output) = build_graph(inputs, build);
+    tensor_context.borrow_mut().backwards(output);
+
+    input_refs
+        .iter()
+        .map(|input| {
+            let tensor = tensor_context.borrow().get_tensor(*input);
+            tensor
+                .grad
+                .unwrap_or_else(|| vec![0.0; tensor.data.len()])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gradcheck_reports_every_element() {
+        let report = gradcheck(
+            vec![(vec![2], vec![1.0, 2.0]), (vec![2], vec![3.0, 4.0])],
+            |tensor_context, inputs| {
+                let product = tensor_context.borrow_mut().mul(inputs[0], inputs[1]);
+                tensor_context.borrow_mut().sum(product)
+            },
+        );
+
+        assert_eq!(report.results.len(), 4);
+        assert_eq!(report.results[0].analytic, 3.0);
+        assert_eq!(report.results[3].analytic, 2.0);
+        assert!(report.passed(1e-6));
+    }
+
+    #[test]
+    fn test_gradcheck_unused_input_has_zero_gradient() {
+        let report = gradcheck(
+            vec![(vec![1], vec![1.0]), (vec![1], vec![5.0])],
+            |tensor_context, inputs| tensor_context.borrow_mut().sum(inputs[0]),
+        );
+
+        assert_eq!(report.results[1].analytic, 0.0);
+        assert!(report.results[1].numeric.abs() < 1e-9);
+        assert!(report.failures(1e-6).is_empty());
+    }
+}
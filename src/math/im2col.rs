@@ -0,0 +1,253 @@
+// How a window slid over the last two axes of an NCHW tensor treats the border
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Padding {
+    // Only positions where the whole window fits inside the input
+    Valid,
+    // Zero padding so the output has ceil(size / stride) positions along each axis
+    Same,
+}
+
+// Kernel size, stride and dilation as (height, width)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub kernel: (usize, usize),
+    pub stride: (usize, usize),
+    pub dilation: (usize, usize),
+    pub padding: Padding,
+}
+
+impl Window {
+    // Stride 1, no dilation and no padding
+    pub fn new(kernel: (usize, usize)) -> Window {
+        Window {
+            kernel,
+            stride: (1, 1),
+            dilation: (1, 1),
+            padding: Padding::Valid,
+        }
+    }
+}
+
+// A window resolved against the shape of its input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WindowDims {
+    pub batch: usize,
+    pub channels: usize,
+    pub height: usize,
+    pub width: usize,
+    pub output_height: usize,
+    pub output_width: usize,
+    // Zeros added before the first row and column
+    pub pad_top: usize,
+    pub pad_left: usize,
+    pub window: Window,
+}
+
+impl WindowDims {
+    pub fn new(shape: &[usize], window: &Window) -> WindowDims {
+        if shape.len() != 4 {
+            panic!("Expected an NCHW tensor, got shape {:?}", shape);
+        }
+        let (kernel_height, kernel_width) = window.kernel;
+        let (stride_height, stride_width) = window.stride;
+        let (dilation_height, dilation_width) = window.dilation;
+        let sizes = [kernel_height, kernel_width, stride_height, stride_width, dilation_height, dilation_width];
+        if sizes.contains(&0) {
+            panic!("Window sizes must be positive, got {:?}", window);
+        }
+
+        // Output size and leading padding along one axis
+        let resolve = |size: usize, kernel: usize, stride: usize, dilation: usize| {
+            let extent = (kernel - 1) * dilation + 1;
+            match window.padding {
+                Padding::Valid => {
+                    if extent > size {
+                        panic!("Window {:?} does not fit in an input of shape {:?}", window, shape);
+                    }
+                    ((size - extent) / stride + 1, 0)
+                }
+                Padding::Same => {
+                    let output = size.div_ceil(stride);
+                    let padding = ((output - 1) * stride + extent).saturating_sub(size);
+                    (output, padding / 2)
+                }
+            }
+        };
+        let (output_height, pad_top) = resolve(shape[2], kernel_height, stride_height, dilation_height);
+        let (output_width, pad_left) = resolve(shape[3], kernel_width, stride_width, dilation_width);
+
+        WindowDims {
+            batch: shape[0],
+            channels: shape[1],
+            height: shape[2],
+            width: shape[3],
+            output_height,
+            output_width,
+            pad_top,
+            pad_left,
+            window: *window,
+        }
+    }
+
+    // Window positions per channel
+    pub fn output_size(&self) -> usize {
+        self.output_height * self.output_width
+    }
+
+    // Elements under one window across every channel
+    pub fn patch_size(&self) -> usize {
+        self.channels * self.window.kernel.0 * self.window.kernel.1
+    }
+
+    // Offset within one channel of element (i, j) of the window at output position (y, x), or
+    // None when it falls in the padding
+    pub fn input_offset(&self, y: usize, x: usize, i: usize, j: usize) -> Option<usize> {
+        let row = (y * self.window.stride.0 + i * self.window.dilation.0).checked_sub(self.pad_top)?;
+        let column = (x * self.window.stride.1 + j * self.window.dilation.1).checked_sub(self.pad_left)?;
+        if row < self.height && column < self.width {
+            Some(row * self.width + column)
+        } else {
+            None
+        }
+    }
+
+    // Shape of the columns built by im2col: one row per patch element and one column per window
+    // position, for every sample
+    pub fn columns_shape(&self) -> Vec<usize> {
+        vec![self.batch, self.patch_size(), self.output_size()]
+    }
+
+    // Calls f with the input index and column index of every element under every window,
+    // skipping those in the padding
+    fn for_each_element(&self, mut f: impl FnMut(usize, usize)) {
+        let (kernel_height, kernel_width) = self.window.kernel;
+        let plane = self.height * self.width;
+        for sample in 0..self.batch {
+            for channel in 0..self.channels {
+                let input_start = (sample * self.channels + channel) * plane;
+                for i in 0..kernel_height {
+                    for j in 0..kernel_width {
+                        let row = (channel * kernel_height + i) * kernel_width + j;
+                        let column_start = (sample * self.patch_size() + row) * self.output_size();
+                        for y in 0..self.output_height {
+                            for x in 0..self.output_width {
+                                if let Some(offset) = self.input_offset(y, x, i, j) {
+                                    f(input_start + offset, column_start + y * self.output_width + x);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Unrolls every window of the input into a column, so a convolution becomes a matrix product
+pub fn forward(dims: &WindowDims, data: &[f64]) -> Vec<f64> {
+    let mut columns = vec![0.0; dims.columns_shape().iter().product()];
+    dims.for_each_element(|input, column| columns[column] = data[input]);
+    columns
+}
+
+// Gradient of the input, adding up the gradients of every column an element was copied into
+pub fn backward(dims: &WindowDims, grad: &[f64]) -> Vec<f64> {
+    let mut input_grad = vec![0.0; dims.batch * dims.channels * dims.height * dims.width];
+    dims.for_each_element(|input, column| input_grad[input] += grad[column]);
+    input_grad
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::gradcheck::gradcheck;
+
+    use super::*;
+
+    #[test]
+    fn test_window_dims() {
+        let dims = WindowDims::new(&[2, 3, 5, 5], &Window::new((3, 3)));
+        assert_eq!((dims.output_height, dims.output_width), (3, 3));
+        assert_eq!(dims.columns_shape(), vec![2, 27, 9]);
+
+        let window = Window {
+            stride: (2, 2),
+            padding: Padding::Same,
+            ..Window::new((3, 3))
+        };
+        let dims = WindowDims::new(&[1, 1, 5, 6], &window);
+        assert_eq!((dims.output_height, dims.output_width), (3, 3));
+        assert_eq!((dims.pad_top, dims.pad_left), (1, 0));
+
+        let window = Window {
+            dilation: (2, 2),
+            ..Window::new((3, 3))
+        };
+        let dims = WindowDims::new(&[1, 1, 7, 5], &window);
+        assert_eq!((dims.output_height, dims.output_width), (3, 1));
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn test_window_too_large() {
+        WindowDims::new(&[1, 1, 2, 2], &Window::new((3, 3)));
+    }
+
+    #[test]
+    fn test_forward() {
+        // One channel
+        // 1 2 3
+        // 4 5 6
+        // 7 8 9
+        let data: Vec<f64> = (1..=9).map(|a| a as f64).collect();
+        let dims = WindowDims::new(&[1, 1, 3, 3], &Window::new((2, 2)));
+        let columns = forward(&dims, &data);
+        #[rustfmt::skip]
+        assert_eq!(columns, vec![
+            1.0, 2.0, 4.0, 5.0,
+            2.0, 3.0, 5.0, 6.0,
+            4.0, 5.0, 7.0, 8.0,
+            5.0, 6.0, 8.0, 9.0,
+        ]);
+
+        let window = Window {
+            padding: Padding::Same,
+            ..Window::new((3, 3))
+        };
+        let dims = WindowDims::new(&[1, 1, 3, 3], &window);
+        let columns = forward(&dims, &data);
+        // The centre element of the kernel sees the input unchanged
+        assert_eq!(columns[4 * 9..5 * 9].to_vec(), data);
+        // The top left element of the kernel sees padding on the first row and column
+        assert_eq!(columns[..9].to_vec(), vec![0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 0.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_backward_counts_overlaps() {
+        let dims = WindowDims::new(&[1, 1, 3, 3], &Window::new((2, 2)));
+        let grad = backward(&dims, &[1.0; 16]);
+        assert_eq!(grad, vec![1.0, 2.0, 1.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0]);
+    }
+
+    #[test]
+    fn test_gradcheck_convolution() {
+        let window = Window {
+            stride: (2, 1),
+            dilation: (1, 2),
+            padding: Padding::Same,
+            ..Window::new((2, 2))
+        };
+        let input: Vec<f64> = (0..2 * 2 * 4 * 4).map(|a| (a as f64 * 0.37).sin()).collect();
+        let weights: Vec<f64> = (0..3 * 8).map(|a| (a as f64 * 0.61).cos()).collect();
+        let report = gradcheck(
+            vec![(vec![2, 2, 4, 4], input), (vec![3, 8], weights)],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                let columns = tensor_context.im2col(inputs[0], window);
+                let product = tensor_context.matmul(inputs[1], columns);
+                let squared = tensor_context.mul(product, product);
+                tensor_context.sum(squared)
+            },
+        );
+        assert!(report.passed(1e-6), "{:?}", report.failures(1e-6));
+    }
+}
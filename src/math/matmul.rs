@@ -0,0 +1,178 @@
+// Dimensions of a (possibly batched) matrix product
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MatMulDims {
+    pub batch: usize,
+    pub rows: usize,
+    pub inner: usize,
+    pub columns: usize,
+    // Whether each operand holds one matrix per batch entry rather than a single shared matrix
+    pub left_batched: bool,
+    pub right_batched: bool,
+}
+
+impl MatMulDims {
+    // Accepts 2-D and 3-D operands; a 2-D operand is shared across every batch of the other one
+    pub fn new(left_shape: &[usize], right_shape: &[usize]) -> MatMulDims {
+        let invalid = || -> ! {
+            panic!("Cannot multiply matrices of shape {:?} and {:?}", left_shape, right_shape)
+        };
+        let split = |shape: &[usize]| match shape.len() {
+            2 => (None, shape[0], shape[1]),
+            3 => (Some(shape[0]), shape[1], shape[2]),
+            _ => invalid(),
+        };
+        let (left_batch, rows, inner) = split(left_shape);
+        let (right_batch, right_inner, columns) = split(right_shape);
+        if inner != right_inner {
+            invalid();
+        }
+        let batch = match (left_batch, right_batch) {
+            (Some(left), Some(right)) if left != right => invalid(),
+            (Some(batch), _) | (None, Some(batch)) => batch,
+            (None, None) => 1,
+        };
+
+        MatMulDims {
+            batch,
+            rows,
+            inner,
+            columns,
+            left_batched: left_batch.is_some(),
+            right_batched: right_batch.is_some(),
+        }
+    }
+
+    pub fn output_shape(&self) -> Vec<usize> {
+        if self.left_batched || self.right_batched {
+            vec![self.batch, self.rows, self.columns]
+        } else {
+            vec![self.rows, self.columns]
+        }
+    }
+
+    pub fn forward(&self, left: &[f64], right: &[f64]) -> Vec<f64> {
+        let mut output = vec![0.0; self.batch * self.rows * self.columns];
+        for batch in 0..self.batch {
+            gemm(
+                &left[self.left_offset(batch)..],
+                false,
+                &right[self.right_offset(batch)..],
+                false,
+                &mut output[batch * self.rows * self.columns..],
+                (self.rows, self.inner, self.columns),
+            );
+        }
+        output
+    }
+
+    // Gradients of both operands given the gradient of the product. A shared 2-D operand
+    // receives the sum of its gradients over the batch.
+    pub fn backward(&self, left: &[f64], right: &[f64], grad: &[f64]) -> (Vec<f64>, Vec<f64>) {
+        let mut left_grad = vec![0.0; left.len()];
+        let mut right_grad = vec![0.0; right.len()];
+        for batch in 0..self.batch {
+            let output_grad = &grad[batch * self.rows * self.columns..];
+            // dL = dC · Rᵀ
+            gemm(
+                output_grad,
+                false,
+                &right[self.right_offset(batch)..],
+                true,
+                &mut left_grad[self.left_offset(batch)..],
+                (self.rows, self.columns, self.inner),
+            );
+            // dR = Lᵀ · dC
+            gemm(
+                &left[self.left_offset(batch)..],
+                true,
+                output_grad,
+                false,
+                &mut right_grad[self.right_offset(batch)..],
+                (self.inner, self.rows, self.columns),
+            );
+        }
+        (left_grad, right_grad)
+    }
+
+    fn left_offset(&self, batch: usize) -> usize {
+        if self.left_batched {
+            batch * self.rows * self.inner
+        } else {
+            0
+        }
+    }
+
+    fn right_offset(&self, batch: usize) -> usize {
+        if self.right_batched {
+            batch * self.inner * self.columns
+        } else {
+            0
+        }
+    }
+}
+
+// Adds a·b to output, where a is m×k and b is k×n once the transpose flags are applied. A
+// transposed operand is read from its untransposed row-major storage.
+fn gemm(
+    a: &[f64],
+    a_transposed: bool,
+    b: &[f64],
+    b_transposed: bool,
+    output: &mut [f64],
+    (m, k, n): (usize, usize, usize),
+) {
+    for i in 0..m {
+        for p in 0..k {
+            let a_value = if a_transposed { a[p * m + i] } else { a[i * k + p] };
+            let row = &mut output[i * n..(i + 1) * n];
+            if b_transposed {
+                row.iter_mut()
+                    .enumerate()
+                    .for_each(|(j, out)| *out += a_value * b[j * k + p]);
+            } else {
+                row.iter_mut()
+                    .zip(b[p * n..(p + 1) * n].iter())
+                    .for_each(|(out, b_value)| *out += a_value * b_value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matmul_dims() {
+        let dims = MatMulDims::new(&[4, 2, 3], &[3, 5]);
+        assert_eq!(dims.output_shape(), vec![4, 2, 5]);
+        assert!(dims.left_batched);
+        assert!(!dims.right_batched);
+
+        assert_eq!(MatMulDims::new(&[2, 3], &[3, 5]).output_shape(), vec![2, 5]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot multiply")]
+    fn test_matmul_dims_inner_mismatch() {
+        MatMulDims::new(&[2, 3], &[2, 3]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot multiply")]
+    fn test_matmul_dims_batch_mismatch() {
+        MatMulDims::new(&[2, 2, 3], &[3, 3, 2]);
+    }
+
+    #[test]
+    fn test_non_finite_values_propagate() {
+        // A zero in the left operand must not hide a NaN or inf in the right one
+        let dims = MatMulDims::new(&[1, 2], &[2, 2]);
+        let output = dims.forward(&[0.0, 1.0], &[f64::NAN, f64::INFINITY, 1.0, 1.0]);
+        assert!(output[0].is_nan());
+        assert!(output[1].is_nan());
+
+        let (_, right_grad) = dims.backward(&[0.0, 1.0], &[1.0, 2.0, 3.0, 4.0], &[f64::NAN, 0.0]);
+        assert!(right_grad[0].is_nan());
+    }
+}
@@ -0,0 +1,239 @@
+// Batch normalization takes statistics per channel, the second axis of a [batch, channels, ...]
+// tensor, over the batch and every axis after the channels. Layer and RMS normalization take them
+// over the last axis of every row.
+
+// Number of channels and the number of elements each channel holds
+fn channel_layout(shape: &[usize]) -> (usize, usize) {
+    if shape.len() < 2 {
+        panic!("Expected a tensor with a batch and a channel axis, got shape {:?}", shape);
+    }
+    (shape[1], shape[2..].iter().product())
+}
+
+fn channel_of(index: usize, channels: usize, inner: usize) -> usize {
+    (index / inner) % channels
+}
+
+// Shape of the moments of a tensor: the means in the first row and the biased variances in the
+// second
+pub fn moments_shape(shape: &[usize]) -> Vec<usize> {
+    vec![2, channel_layout(shape).0]
+}
+
+pub fn moments(shape: &[usize], data: &[f64]) -> Vec<f64> {
+    let (channels, inner) = channel_layout(shape);
+    let count = (data.len() / channels).max(1) as f64;
+    let mut means = vec![0.0; channels];
+    for (index, value) in data.iter().enumerate() {
+        means[channel_of(index, channels, inner)] += value / count;
+    }
+    let mut variances = vec![0.0; channels];
+    for (index, value) in data.iter().enumerate() {
+        let channel = channel_of(index, channels, inner);
+        variances[channel] += (value - means[channel]).powi(2) / count;
+    }
+    means.extend(variances);
+    means
+}
+
+// Gradient of the input given the gradient of its moments. The variance depends on the mean too,
+// but that term sums to zero over each channel.
+pub fn moments_backward(shape: &[usize], data: &[f64], moments: &[f64], grad: &[f64]) -> Vec<f64> {
+    let (channels, inner) = channel_layout(shape);
+    let count = (data.len() / channels).max(1) as f64;
+    data.iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let channel = channel_of(index, channels, inner);
+            let mean = moments[channel];
+            (grad[channel] + grad[channels + channel] * 2.0 * (value - mean)) / count
+        })
+        .collect()
+}
+
+// Shifts and scales every channel to zero mean and unit variance given its moments
+pub fn normalize(shape: &[usize], data: &[f64], moments: &[f64], epsilon: f64) -> Vec<f64> {
+    let (channels, inner) = channel_layout(shape);
+    data.iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let channel = channel_of(index, channels, inner);
+            (value - moments[channel]) / (moments[channels + channel] + epsilon).sqrt()
+        })
+        .collect()
+}
+
+// Gradients of the input and of the moments given the gradient of the normalized tensor
+pub fn normalize_backward(
+    shape: &[usize],
+    data: &[f64],
+    moments: &[f64],
+    epsilon: f64,
+    grad: &[f64],
+) -> (Vec<f64>, Vec<f64>) {
+    let (channels, inner) = channel_layout(shape);
+    let mut input_grad = Vec::with_capacity(data.len());
+    let mut moments_grad = vec![0.0; 2 * channels];
+    for (index, (value, grad)) in data.iter().zip(grad.iter()).enumerate() {
+        let channel = channel_of(index, channels, inner);
+        let variance = moments[channels + channel] + epsilon;
+        let deviation = value - moments[channel];
+        input_grad.push(grad / variance.sqrt());
+        moments_grad[channel] -= grad / variance.sqrt();
+        moments_grad[channels + channel] -= grad * deviation / (2.0 * variance.powf(1.5));
+    }
+    (input_grad, moments_grad)
+}
+
+// Length of the last axis, which every row is normalized over
+fn row_size(shape: &[usize]) -> usize {
+    match shape.last() {
+        Some(size) if *size > 0 => *size,
+        _ => panic!("Cannot normalize over the last axis of a tensor of shape {:?}", shape),
+    }
+}
+
+// Shifts and scales every row to zero mean and unit variance
+pub fn layer_normalize(shape: &[usize], data: &[f64], epsilon: f64) -> Vec<f64> {
+    data.chunks(row_size(shape))
+        .flat_map(|row| {
+            let mean = row.iter().sum::<f64>() / row.len() as f64;
+            let variance = row.iter().map(|a| (a - mean).powi(2)).sum::<f64>() / row.len() as f64;
+            let deviation = (variance + epsilon).sqrt();
+            row.iter().map(move |a| (a - mean) / deviation)
+        })
+        .collect()
+}
+
+// dx = (dy - mean(dy) - y·mean(dy·y)) / σ for every row
+pub fn layer_normalize_backward(
+    shape: &[usize],
+    data: &[f64],
+    output: &[f64],
+    epsilon: f64,
+    grad: &[f64],
+) -> Vec<f64> {
+    let size = row_size(shape);
+    let mut input_grad = Vec::with_capacity(data.len());
+    for ((row, output), grad) in data.chunks(size).zip(output.chunks(size)).zip(grad.chunks(size)) {
+        let mean = row.iter().sum::<f64>() / size as f64;
+        let variance = row.iter().map(|a| (a - mean).powi(2)).sum::<f64>() / size as f64;
+        let deviation = (variance + epsilon).sqrt();
+        let grad_mean = grad.iter().sum::<f64>() / size as f64;
+        let projection = grad.iter().zip(output.iter()).map(|(g, y)| g * y).sum::<f64>() / size as f64;
+        input_grad.extend(
+            grad.iter()
+                .zip(output.iter())
+                .map(|(g, y)| (g - grad_mean - y * projection) / deviation),
+        );
+    }
+    input_grad
+}
+
+// Scales every row by the reciprocal of its root mean square, without centering it
+pub fn rms_normalize(shape: &[usize], data: &[f64], epsilon: f64) -> Vec<f64> {
+    data.chunks(row_size(shape))
+        .flat_map(|row| {
+            let rms = (row.iter().map(|a| a * a).sum::<f64>() / row.len() as f64 + epsilon).sqrt();
+            row.iter().map(move |a| a / rms)
+        })
+        .collect()
+}
+
+// dx = (dy - y·mean(dy·y)) / rms for every row
+pub fn rms_normalize_backward(
+    shape: &[usize],
+    data: &[f64],
+    output: &[f64],
+    epsilon: f64,
+    grad: &[f64],
+) -> Vec<f64> {
+    let size = row_size(shape);
+    let mut input_grad = Vec::with_capacity(data.len());
+    for ((row, output), grad) in data.chunks(size).zip(output.chunks(size)).zip(grad.chunks(size)) {
+        let rms = (row.iter().map(|a| a * a).sum::<f64>() / size as f64 + epsilon).sqrt();
+        let projection = grad.iter().zip(output.iter()).map(|(g, y)| g * y).sum::<f64>() / size as f64;
+        input_grad.extend(grad.iter().zip(output.iter()).map(|(g, y)| (g - y * projection) / rms));
+    }
+    input_grad
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::gradcheck::gradcheck;
+
+    use super::*;
+
+    #[test]
+    fn test_moments() {
+        // Two samples of two channels with two elements each
+        let data = vec![1.0, 3.0, 10.0, 10.0, 5.0, 7.0, 20.0, 20.0];
+        let moments = moments(&[2, 2, 2], &data);
+        assert_eq!(moments, vec![4.0, 15.0, 5.0, 25.0]);
+        assert_eq!(moments_shape(&[2, 2, 2]), vec![2, 2]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let data = vec![1.0, 2.0, 3.0, 10.0];
+        let moments = moments(&[2, 2], &data);
+        let normalized = normalize(&[2, 2], &data, &moments, 0.0);
+        assert_eq!(normalized, vec![-1.0, -1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected a tensor with a batch and a channel axis")]
+    fn test_moments_without_channels() {
+        moments(&[3], &[1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn test_layer_normalize() {
+        let normalized = layer_normalize(&[2, 2], &[1.0, 3.0, -5.0, 5.0], 0.0);
+        assert_eq!(normalized, vec![-1.0, 1.0, -1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_rms_normalize() {
+        let normalized = rms_normalize(&[1, 2], &[3.0, -4.0], 0.0);
+        let rms = 12.5_f64.sqrt();
+        assert_eq!(normalized, vec![3.0 / rms, -4.0 / rms]);
+    }
+
+    #[test]
+    fn test_gradcheck_batch_normalization() {
+        let data: Vec<f64> = (0..3 * 2 * 2 * 2).map(|a| (a as f64 * 0.9).sin() * 2.0).collect();
+        let weights: Vec<f64> = (0..3 * 2 * 2 * 2).map(|a| (a as f64 * 0.4).cos()).collect();
+        let report = gradcheck(
+            vec![(vec![3, 2, 2, 2], data), (vec![3, 2, 2, 2], weights)],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                let moments = tensor_context.channel_moments(inputs[0]);
+                let normalized = tensor_context.normalize(inputs[0], moments, 1e-3);
+                // Weighting the outputs keeps the loss from being constant in the input
+                let weighted = tensor_context.mul(normalized, inputs[1]);
+                tensor_context.sum(weighted)
+            },
+        );
+        assert!(report.passed(1e-5), "{:?}", report.failures(1e-5));
+    }
+
+    #[test]
+    fn test_gradcheck_layer_and_rms_normalization() {
+        // Leading batch and sequence axes
+        let data: Vec<f64> = (0..2 * 3 * 4).map(|a| (a as f64 * 0.7).sin() * 3.0).collect();
+        let weights: Vec<f64> = (0..2 * 3 * 4).map(|a| (a as f64 * 0.3).cos()).collect();
+        let report = gradcheck(
+            vec![(vec![2, 3, 4], data), (vec![2, 3, 4], weights)],
+            |tensor_context, inputs| {
+                let mut tensor_context = tensor_context.borrow_mut();
+                let layer_normalized = tensor_context.layer_normalize(inputs[0], 1e-5);
+                let rms_normalized = tensor_context.rms_normalize(inputs[0], 1e-5);
+                let sum = tensor_context.add(layer_normalized, rms_normalized);
+                let weighted = tensor_context.mul(sum, inputs[1]);
+                tensor_context.sum(weighted)
+            },
+        );
+        assert!(report.passed(1e-5), "{:?}", report.failures(1e-5));
+    }
+}
@@ -0,0 +1,155 @@
+use super::im2col::WindowDims;
+
+// Shape of a pooled NCHW tensor
+pub fn output_shape(dims: &WindowDims) -> Vec<usize> {
+    vec![dims.batch, dims.channels, dims.output_height, dims.output_width]
+}
+
+// Input indices under the window at every output position, in output order. Padding is left out,
+// so windows over the border hold fewer elements.
+fn windows(dims: &WindowDims) -> Vec<Vec<usize>> {
+    let (kernel_height, kernel_width) = dims.window.kernel;
+    let plane = dims.height * dims.width;
+    let mut windows = Vec::with_capacity(dims.batch * dims.channels * dims.output_size());
+    for channel_start in (0..dims.batch * dims.channels).map(|channel| channel * plane) {
+        for y in 0..dims.output_height {
+            for x in 0..dims.output_width {
+                let window = (0..kernel_height)
+                    .flat_map(|i| (0..kernel_width).map(move |j| (i, j)))
+                    .filter_map(|(i, j)| dims.input_offset(y, x, i, j))
+                    .map(|offset| channel_start + offset)
+                    .collect();
+                windows.push(window);
+            }
+        }
+    }
+    windows
+}
+
+// Index of the largest element of a window, taking the first on ties
+fn argmax(window: &[usize], data: &[f64]) -> Option<usize> {
+    window
+        .iter()
+        .cloned()
+        .reduce(|best, index| if data[index] > data[best] { index } else { best })
+}
+
+pub fn max_forward(dims: &WindowDims, data: &[f64]) -> Vec<f64> {
+    windows(dims)
+        .iter()
+        .map(|window| argmax(window, data).map_or(0.0, |index| data[index]))
+        .collect()
+}
+
+// Routes the gradient of every output to the input element it was taken from
+pub fn max_backward(dims: &WindowDims, data: &[f64], grad: &[f64]) -> Vec<f64> {
+    let mut input_grad = vec![0.0; data.len()];
+    for (window, grad) in windows(dims).iter().zip(grad.iter()) {
+        if let Some(index) = argmax(window, data) {
+            input_grad[index] += grad;
+        }
+    }
+    input_grad
+}
+
+// Mean of the elements under each window, not counting padding
+pub fn average_forward(dims: &WindowDims, data: &[f64]) -> Vec<f64> {
+    windows(dims)
+        .iter()
+        .map(|window| window.iter().map(|index| data[*index]).sum::<f64>() / window.len().max(1) as f64)
+        .collect()
+}
+
+// Spreads the gradient of every output evenly over the elements of its window
+pub fn average_backward(dims: &WindowDims, grad: &[f64]) -> Vec<f64> {
+    let mut input_grad = vec![0.0; dims.batch * dims.channels * dims.height * dims.width];
+    for (window, grad) in windows(dims).iter().zip(grad.iter()) {
+        for index in window {
+            input_grad[*index] += grad / window.len() as f64;
+        }
+    }
+    input_grad
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::math::{
+        gradcheck::gradcheck,
+        im2col::{Padding, Window},
+    };
+
+    use super::*;
+
+    // One channel
+    //  1  2  3  4
+    //  5  6  7  8
+    //  9 10 11 12
+    // 13 14 15 16
+    fn input() -> Vec<f64> {
+        (1..=16).map(|a| a as f64).collect()
+    }
+
+    fn pooling_window() -> Window {
+        Window {
+            stride: (2, 2),
+            ..Window::new((2, 2))
+        }
+    }
+
+    #[test]
+    fn test_max_pool() {
+        let dims = WindowDims::new(&[1, 1, 4, 4], &pooling_window());
+        assert_eq!(output_shape(&dims), vec![1, 1, 2, 2]);
+        assert_eq!(max_forward(&dims, &input()), vec![6.0, 8.0, 14.0, 16.0]);
+
+        let grad = max_backward(&dims, &input(), &[1.0, 2.0, 3.0, 4.0]);
+        let mut expected = vec![0.0; 16];
+        expected[5] = 1.0;
+        expected[7] = 2.0;
+        expected[13] = 3.0;
+        expected[15] = 4.0;
+        assert_eq!(grad, expected);
+    }
+
+    #[test]
+    fn test_average_pool() {
+        let dims = WindowDims::new(&[1, 1, 4, 4], &pooling_window());
+        assert_eq!(average_forward(&dims, &input()), vec![3.5, 5.5, 11.5, 13.5]);
+        let grad = average_backward(&dims, &[4.0, 0.0, 0.0, 0.0]);
+        assert_eq!(grad[..6].to_vec(), vec![1.0, 1.0, 0.0, 0.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_padding_is_not_counted() {
+        let window = Window {
+            stride: (2, 2),
+            padding: Padding::Same,
+            ..Window::new((3, 3))
+        };
+        // Padded by one row and column on each side
+        let dims = WindowDims::new(&[1, 1, 3, 3], &window);
+        let data: Vec<f64> = (1..=9).map(|a| -(a as f64)).collect();
+        // The top left window covers -1, -2, -4 and -5
+        assert_eq!(max_forward(&dims, &data)[0], -1.0);
+        assert_eq!(average_forward(&dims, &data)[0], -3.0);
+    }
+
+    #[test]
+    fn test_gradcheck_pooling() {
+        let window = Window {
+            stride: (1, 2),
+            padding: Padding::Same,
+            ..Window::new((2, 3))
+        };
+        // Distinct values so no maximum is tied
+        let data: Vec<f64> = (0..2 * 2 * 3 * 4).map(|a| (a as f64 * 1.3).sin() + a as f64 * 0.01).collect();
+        let report = gradcheck(vec![(vec![2, 2, 3, 4], data)], |tensor_context, inputs| {
+            let mut tensor_context = tensor_context.borrow_mut();
+            let max = tensor_context.max_pool(inputs[0], window);
+            let average = tensor_context.avg_pool(inputs[0], window);
+            let product = tensor_context.mul(max, average);
+            tensor_context.sum(product)
+        });
+        assert!(report.passed(1e-6), "{:?}", report.failures(1e-6));
+    }
+}
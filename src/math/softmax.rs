@@ -0,0 +1,81 @@
+// Softmax along one axis of a row-major tensor. The axis may be negative to count from the end.
+
+// Sizes of the axes before the softmax axis, of the axis itself and of the axes after it
+fn split_axis(shape: &[usize], axis: isize) -> (usize, usize, usize) {
+    let rank = shape.len() as isize;
+    let resolved = if axis < 0 { axis + rank } else { axis };
+    if resolved < 0 || resolved >= rank {
+        panic!("Cannot take softmax over axis {} of shape {:?}", axis, shape);
+    }
+    let resolved = resolved as usize;
+    (
+        shape[..resolved].iter().product(),
+        shape[resolved],
+        shape[resolved + 1..].iter().product(),
+    )
+}
+
+// Calls f with the flat indices of every line of values along the axis
+fn for_each_line(shape: &[usize], axis: isize, mut f: impl FnMut(&[usize])) {
+    let (outer, size, inner) = split_axis(shape, axis);
+    let mut indices = vec![0; size];
+    for outer_index in 0..outer {
+        for inner_index in 0..inner {
+            let start = outer_index * size * inner + inner_index;
+            indices
+                .iter_mut()
+                .enumerate()
+                .for_each(|(i, index)| *index = start + i * inner);
+            f(&indices);
+        }
+    }
+}
+
+pub fn forward(shape: &[usize], data: &[f64], axis: isize) -> Vec<f64> {
+    let mut output = vec![0.0; data.len()];
+    for_each_line(shape, axis, |indices| {
+        // Subtracting the maximum keeps exp from overflowing without changing the result
+        let max = indices.iter().map(|i| data[*i]).fold(f64::NEG_INFINITY, f64::max);
+        let sum = indices.iter().map(|i| (data[*i] - max).exp()).sum::<f64>();
+        indices
+            .iter()
+            .for_each(|i| output[*i] = (data[*i] - max).exp() / sum);
+    });
+    output
+}
+
+// Jacobian-vector product of the softmax given its output: y_i * (g_i - Σ_j g_j * y_j)
+pub fn backward(shape: &[usize], output: &[f64], grad: &[f64], axis: isize) -> Vec<f64> {
+    let mut input_grad = vec![0.0; output.len()];
+    for_each_line(shape, axis, |indices| {
+        let dot = indices.iter().map(|i| grad[*i] * output[*i]).sum::<f64>();
+        indices
+            .iter()
+            .for_each(|i| input_grad[*i] = output[*i] * (grad[*i] - dot));
+    });
+    input_grad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forward_over_first_axis() {
+        let output = forward(&[2, 2], &[0.0, 1000.0, 0.0, 1000.0], 0);
+        assert_eq!(output, vec![0.5, 0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_forward_over_last_axis() {
+        let output = forward(&[1, 2], &[0.0, 2.0_f64.ln()], -1);
+        assert!((output[0] - 1.0 / 3.0).abs() < 1e-12);
+        assert!((output[1] - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot take softmax")]
+    fn test_invalid_axis() {
+        forward(&[2, 2], &[0.0; 4], 2);
+    }
+}
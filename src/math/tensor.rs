@@ -5,7 +5,11 @@ use std::{
     rc::Rc,
 };
 
-use super::tensor_context::{TensorContext, TensorRef};
+use super::{
+    custom_op::CustomOpRef,
+    im2col::Window,
+    tensor_context::{TensorContext, TensorRef},
+};
 
 #[derive(Debug, Clone)]
 pub struct Tensor {
@@ -55,18 +59,97 @@ pub enum Operation {
     Add(Vec<TensorRef>),
     Sub(TensorRef, TensorRef),
     Mul(TensorRef, TensorRef),
-    Div,
-    Exp,
+    Div(TensorRef, TensorRef),
+    Exp(TensorRef),
     Pow(TensorRef, f64),
-    Log,
+    Log(TensorRef),
     Sum(TensorRef),
-    Mean,
-    Dot,
+    Mean(TensorRef),
+    Dot(TensorRef, TensorRef),
+    MatMul(TensorRef, TensorRef),
     Tanh(TensorRef),
-    Transpose,
-    Reshape,
-    Slice,
+    Transpose(TensorRef),
+    Reshape(TensorRef, Vec<usize>),
+    // Input and the shape of one sample; the leading batch axis is taken from the input
+    ReshapeSamples(TensorRef, Vec<usize>),
+    Slice(TensorRef, usize, usize),
     ReLU(TensorRef),
+    Sigmoid(TensorRef),
+    LeakyReLU(TensorRef, f64),
+    Softmax(TensorRef, isize),
+    GELU(TensorRef),
+    SiLU(TensorRef),
+    ELU(TensorRef, f64),
+    SELU(TensorRef),
+    Softplus(TensorRef),
+    Mish(TensorRef),
+    HardTanh(TensorRef, f64, f64),
     Concat(Vec<TensorRef>),
+    Im2Col(TensorRef, Window),
+    MaxPool(TensorRef, Window),
+    AvgPool(TensorRef, Window),
+    ChannelMoments(TensorRef),
+    // Input, its channel moments and the epsilon added to the variance
+    Normalize(TensorRef, TensorRef, f64),
+    // Table, token indices and the padding index
+    Embedding(TensorRef, TensorRef, Option<usize>),
+    // Input and the epsilon added to the variance or mean square of every row
+    LayerNormalize(TensorRef, f64),
+    RMSNormalize(TensorRef, f64),
+    // A registered custom operation and its inputs
+    Custom(CustomOpRef, Vec<TensorRef>),
+    // Predictions, targets and whether the predictions are logits
+    CrossEntropy(TensorRef, TensorRef, bool),
+    // Predictions, class indices and whether the predictions are logits
+    SparseCrossEntropy(TensorRef, TensorRef, bool),
     Composite(Vec<(Operation, TensorRef)>),
 }
+
+impl Operation {
+    // Returns the tensors this operation reads from
+    pub fn inputs(&self) -> Vec<TensorRef> {
+        match self {
+            Operation::Add(predecessors)
+            | Operation::Concat(predecessors)
+            | Operation::Custom(_, predecessors) => predecessors.clone(),
+            Operation::Sub(left, right)
+            | Operation::Mul(left, right)
+            | Operation::Div(left, right)
+            | Operation::Dot(left, right)
+            | Operation::MatMul(left, right)
+            | Operation::Normalize(left, right, _)
+            | Operation::Embedding(left, right, _)
+            | Operation::CrossEntropy(left, right, _)
+            | Operation::SparseCrossEntropy(left, right, _) => vec![*left, *right],
+            Operation::Exp(tensor)
+            | Operation::Pow(tensor, _)
+            | Operation::Log(tensor)
+            | Operation::Sum(tensor)
+            | Operation::Mean(tensor)
+            | Operation::Tanh(tensor)
+            | Operation::Transpose(tensor)
+            | Operation::Reshape(tensor, _)
+            | Operation::ReshapeSamples(tensor, _)
+            | Operation::Slice(tensor, _, _)
+            | Operation::Im2Col(tensor, _)
+            | Operation::MaxPool(tensor, _)
+            | Operation::AvgPool(tensor, _)
+            | Operation::ChannelMoments(tensor)
+            | Operation::LayerNormalize(tensor, _)
+            | Operation::RMSNormalize(tensor, _)
+            | Operation::ReLU(tensor)
+            | Operation::Sigmoid(tensor)
+            | Operation::LeakyReLU(tensor, _)
+            | Operation::Softmax(tensor, _)
+            | Operation::GELU(tensor)
+            | Operation::SiLU(tensor)
+            | Operation::ELU(tensor, _)
+            | Operation::SELU(tensor)
+            | Operation::Softplus(tensor)
+            | Operation::Mish(tensor)
+            | Operation::HardTanh(tensor, _, _) => vec![*tensor],
+            // The wrapped operations are already on the tape, so only the final output is read
+            Operation::Composite(operations) => vec![operations.last().unwrap().1],
+        }
+    }
+}
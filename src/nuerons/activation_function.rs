@@ -1,10 +1,24 @@
-
+use crate::math::custom_op::CustomOpRef;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum ActivationFunction {
     Sigmoid,
     ReLU,
-    LeakyReLU,
+    // Slope applied to negative inputs
+    LeakyReLU(f64),
     Tanh,
-    Softmax,
+    // Axis to normalise over, counting from the end when negative
+    Softmax(isize),
+    GELU,
+    // Also known as swish
+    SiLU,
+    // Scale of the negative saturation value
+    ELU(f64),
+    SELU,
+    Softplus,
+    Mish,
+    // Lower and upper bounds the input is clamped to
+    HardTanh(f64, f64),
+    // A single input operation registered with the tensor context
+    Custom(CustomOpRef),
 }
\ No newline at end of file